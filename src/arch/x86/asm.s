@@ -1,10 +1,3 @@
-	.text
-        .globl _dryad_fini
-        .type _dryad_fini, @function
-_dryad_fini:
-	leaq (%rip), %rax
-        retq
-
 	// TODO: fix in lib.rs: _start needs to get a stack and argc that looks like it was executed directly, i believe this might be the final cause of the segfault in dynamic linking, because certain arguments in the stack are too high?
 	.text
         .globl _start
@@ -15,17 +8,8 @@ _start:
 //	mov %rsp, %rbp ; we shouldn't need to save rbp
 	andq $~15, %rsp
         callq _dryad_init
-	/*
-	mov %rax, %rcx
-	callq _dryad_fini
-	movq %rax, %rdx
-	movq %rcx, %rax
-
-	// crashes here: 
-	__new_exitfn
-
-	*/
-	movq $0, %rdx
+	// the program's libc registers this with atexit, to run every object's finalizers
+	leaq _dryad_fini(%rip), %rdx
         jmpq *%rax
         retq
 
@@ -84,7 +68,98 @@ _dryad_resolve_symbol:
 	add    $0x18,%rsp
 	jmpq *%r11
 	nopl   0x0(%rax)
+
+	// dlopen and dlsym need to know who called them, for the caller's $ORIGIN and DT_RUNPATH, and for RTLD_NEXT; that's the return address on top of the stack, which is passed along as the third argument
+	.text
+        .globl dlopen
+        .type dlopen, @function
+dlopen:
+	mov    (%rsp),%rdx
+	jmp    dryad_dlopen
+
+	.text
+        .globl dlsym
+        .type dlsym, @function
+dlsym:
+	mov    (%rsp),%rdx
+	jmp    dryad_dlsym
 	
+	// TLS descriptor resolvers, see http://people.redhat.com/aoliva/writeups/TLS/RFC-TLSDESC-x86.txt
+	// the code calls *(%rax) with %rax pointing at the descriptor, expects the variable's offset from the thread pointer back in %rax,
+	// and that _every_ other register is preserved
+
+	// the variable is in the static TLS area, so the descriptor's argument already is its offset from the thread pointer
+	.text
+        .globl _dryad_tlsdesc_static
+        .type _dryad_tlsdesc_static, @function
+_dryad_tlsdesc_static:
+	mov    0x8(%rax),%rax
+	retq
+
+	// the descriptor's argument is a tls_index for __tls_get_addr, which we ask and then make thread pointer relative;
+	// the program's vector registers can be anything up to zmm31 and the AVX-512 opmasks, so we save whatever state the kernel does with xsave, sized by cpu_features, or just the SSE state with fxsave if there's no xsave
+	.text
+        .globl _dryad_tlsdesc_dynamic
+        .type _dryad_tlsdesc_dynamic, @function
+_dryad_tlsdesc_dynamic:
+	push   %rbx
+	mov    %rsp,%rbx
+	sub    $0x40,%rsp
+	mov    %rdi,(%rsp)
+	mov    %rsi,0x8(%rsp)
+	mov    %rdx,0x10(%rsp)
+	mov    %rcx,0x18(%rsp)
+	mov    %r8,0x20(%rsp)
+	mov    %r9,0x28(%rsp)
+	mov    %r10,0x30(%rsp)
+	mov    %r11,0x38(%rsp)
+	mov    0x8(%rax),%rdi
+	mov    DRYAD_XSAVE_SIZE(%rip),%rcx
+	test   %rcx,%rcx
+	jz     1f
+	// the xsave area has to be 64 byte aligned, and xrstor faults unless the parts of its header xsave doesn't write are zero
+	sub    %rcx,%rsp
+	and    $0xffffffffffffffc0,%rsp
+	xor    %eax,%eax
+	mov    %rax,0x200(%rsp)
+	mov    %rax,0x208(%rsp)
+	mov    %rax,0x210(%rsp)
+	mov    %rax,0x218(%rsp)
+	mov    %rax,0x220(%rsp)
+	mov    %rax,0x228(%rsp)
+	mov    %rax,0x230(%rsp)
+	mov    %rax,0x238(%rsp)
+	mov    $-1,%eax
+	mov    $-1,%edx
+	xsave  (%rsp)
+	callq  __tls_get_addr
+	mov    %rax,%rsi
+	mov    $-1,%eax
+	mov    $-1,%edx
+	xrstor (%rsp)
+	mov    %rsi,%rax
+	jmp    2f
+1:
+	sub    $0x200,%rsp
+	and    $0xfffffffffffffff0,%rsp
+	fxsave (%rsp)
+	callq  __tls_get_addr
+	fxrstor (%rsp)
+2:
+	sub    %fs:0,%rax
+	lea    -0x40(%rbx),%rsp
+	mov    0x38(%rsp),%r11
+	mov    0x30(%rsp),%r10
+	mov    0x28(%rsp),%r9
+	mov    0x20(%rsp),%r8
+	mov    0x18(%rsp),%rcx
+	mov    0x10(%rsp),%rdx
+	mov    0x8(%rsp),%rsi
+	mov    (%rsp),%rdi
+	mov    %rbx,%rsp
+	pop    %rbx
+	retq
+
 	.text
         .globl _print
         .type _print, @function
@@ -0,0 +1,234 @@
+/// The CPU features IFUNC resolvers select their implementation with, e.g., glibc's `memcpy` and `strlen` pick between SSE2, AVX2 and EVEX variants.
+/// They're read once, with `cpuid`, before anything is relocated, and every resolver gets `AT_HWCAP`, `AT_HWCAP2` and a pointer to them; see `resolve::ifunc`.
+///
+/// A feature the CPU has isn't necessarily usable: AVX and AVX-512 also need the kernel to save their register state on a context switch, which it advertises in `XCR0`.
+
+/// Indices into `CpuFeatures::cpuid`
+pub const CPUID_INDEX_1: usize = 0;
+pub const CPUID_INDEX_7: usize = 1;
+pub const CPUID_INDEX_80000001: usize = 2;
+pub const CPUID_INDEX_MAX: usize = 3;
+
+/// `XCR0` bits: the SSE and AVX state, and the AVX-512 opmask, upper ZMM0-15 and ZMM16-31 state
+const XSTATE_SSE: u64 = 1 << 1;
+const XSTATE_YMM: u64 = 1 << 2;
+const XSTATE_OPMASK: u64 = 1 << 5;
+const XSTATE_ZMM_HI256: u64 = 1 << 6;
+const XSTATE_HI16_ZMM: u64 = 1 << 7;
+const XSTATE_AVX: u64 = XSTATE_SSE | XSTATE_YMM;
+const XSTATE_AVX512: u64 = XSTATE_AVX | XSTATE_OPMASK | XSTATE_ZMM_HI256 | XSTATE_HI16_ZMM;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Register {
+    Eax,
+    Ebx,
+    Ecx,
+    Edx,
+}
+
+/// A feature bit: which leaf, see the `CPUID_INDEX_*`, which register, and which bit, along with the `XCR0` state it needs to be usable
+#[derive(Clone, Copy, Debug)]
+pub struct Feature {
+    pub index: usize,
+    pub register: Register,
+    pub bit: u32,
+    pub xstate: u64,
+}
+
+pub const SSE3: Feature = Feature { index: CPUID_INDEX_1, register: Register::Ecx, bit: 0, xstate: 0 };
+pub const PCLMULQDQ: Feature = Feature { index: CPUID_INDEX_1, register: Register::Ecx, bit: 1, xstate: 0 };
+pub const SSSE3: Feature = Feature { index: CPUID_INDEX_1, register: Register::Ecx, bit: 9, xstate: 0 };
+pub const FMA: Feature = Feature { index: CPUID_INDEX_1, register: Register::Ecx, bit: 12, xstate: XSTATE_AVX };
+pub const CMPXCHG16B: Feature = Feature { index: CPUID_INDEX_1, register: Register::Ecx, bit: 13, xstate: 0 };
+pub const SSE4_1: Feature = Feature { index: CPUID_INDEX_1, register: Register::Ecx, bit: 19, xstate: 0 };
+pub const SSE4_2: Feature = Feature { index: CPUID_INDEX_1, register: Register::Ecx, bit: 20, xstate: 0 };
+pub const MOVBE: Feature = Feature { index: CPUID_INDEX_1, register: Register::Ecx, bit: 22, xstate: 0 };
+pub const POPCNT: Feature = Feature { index: CPUID_INDEX_1, register: Register::Ecx, bit: 23, xstate: 0 };
+pub const AES: Feature = Feature { index: CPUID_INDEX_1, register: Register::Ecx, bit: 25, xstate: 0 };
+pub const XSAVE: Feature = Feature { index: CPUID_INDEX_1, register: Register::Ecx, bit: 26, xstate: 0 };
+pub const OSXSAVE: Feature = Feature { index: CPUID_INDEX_1, register: Register::Ecx, bit: 27, xstate: 0 };
+pub const AVX: Feature = Feature { index: CPUID_INDEX_1, register: Register::Ecx, bit: 28, xstate: XSTATE_AVX };
+pub const F16C: Feature = Feature { index: CPUID_INDEX_1, register: Register::Ecx, bit: 29, xstate: XSTATE_AVX };
+pub const RDRAND: Feature = Feature { index: CPUID_INDEX_1, register: Register::Ecx, bit: 30, xstate: 0 };
+pub const CMOV: Feature = Feature { index: CPUID_INDEX_1, register: Register::Edx, bit: 15, xstate: 0 };
+pub const SSE: Feature = Feature { index: CPUID_INDEX_1, register: Register::Edx, bit: 25, xstate: 0 };
+pub const SSE2: Feature = Feature { index: CPUID_INDEX_1, register: Register::Edx, bit: 26, xstate: 0 };
+pub const BMI1: Feature = Feature { index: CPUID_INDEX_7, register: Register::Ebx, bit: 3, xstate: 0 };
+pub const AVX2: Feature = Feature { index: CPUID_INDEX_7, register: Register::Ebx, bit: 5, xstate: XSTATE_AVX };
+pub const BMI2: Feature = Feature { index: CPUID_INDEX_7, register: Register::Ebx, bit: 8, xstate: 0 };
+pub const ERMS: Feature = Feature { index: CPUID_INDEX_7, register: Register::Ebx, bit: 9, xstate: 0 };
+pub const RTM: Feature = Feature { index: CPUID_INDEX_7, register: Register::Ebx, bit: 11, xstate: 0 };
+pub const AVX512F: Feature = Feature { index: CPUID_INDEX_7, register: Register::Ebx, bit: 16, xstate: XSTATE_AVX512 };
+pub const AVX512DQ: Feature = Feature { index: CPUID_INDEX_7, register: Register::Ebx, bit: 17, xstate: XSTATE_AVX512 };
+pub const AVX512CD: Feature = Feature { index: CPUID_INDEX_7, register: Register::Ebx, bit: 28, xstate: XSTATE_AVX512 };
+pub const AVX512BW: Feature = Feature { index: CPUID_INDEX_7, register: Register::Ebx, bit: 30, xstate: XSTATE_AVX512 };
+pub const AVX512VL: Feature = Feature { index: CPUID_INDEX_7, register: Register::Ebx, bit: 31, xstate: XSTATE_AVX512 };
+pub const FSRM: Feature = Feature { index: CPUID_INDEX_7, register: Register::Edx, bit: 4, xstate: 0 };
+pub const LAHF: Feature = Feature { index: CPUID_INDEX_80000001, register: Register::Ecx, bit: 0, xstate: 0 };
+pub const LZCNT: Feature = Feature { index: CPUID_INDEX_80000001, register: Register::Ecx, bit: 5, xstate: 0 };
+
+/// The registers a `cpuid` leaf returns
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cpuid {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+}
+
+impl Cpuid {
+    #[inline]
+    fn get(&self, register: Register) -> u32 {
+        match register {
+            Register::Eax => self.eax,
+            Register::Ebx => self.ebx,
+            Register::Ecx => self.ecx,
+            Register::Edx => self.edx,
+        }
+    }
+}
+
+/// What a resolver's third argument points at; `size` comes first, so a resolver can tell which of the fields after it are there
+#[repr(C)]
+#[derive(Debug)]
+pub struct CpuFeatures {
+    pub size: u64,
+    pub hwcap: u64,
+    pub hwcap2: u64,
+    /// The highest basic `cpuid` leaf
+    pub max_cpuid: u32,
+    pub family: u32,
+    pub model: u32,
+    pub stepping: u32,
+    /// Leaves 1, 7 (subleaf 0) and 0x80000001, or zeroes for a leaf the CPU doesn't have
+    pub cpuid: [Cpuid; CPUID_INDEX_MAX],
+    /// The state the kernel saves, or 0 if it doesn't support `xgetbv`
+    pub xcr0: u64,
+}
+
+const EMPTY: Cpuid = Cpuid { eax: 0, ebx: 0, ecx: 0, edx: 0 };
+
+/// The size of the `xsave` area for every state the kernel saves, i.e., `cpuid` leaf 0xd's `%ebx`, or 0 if `xsave` isn't usable; `_dryad_tlsdesc_dynamic` in `asm.s` saves the program's registers with it. Set by `init`
+#[no_mangle]
+pub static mut DRYAD_XSAVE_SIZE: u64 = 0;
+
+/// Set once by `init`, before any resolver runs, and never written again
+static mut FEATURES: CpuFeatures = CpuFeatures {
+    size: 0,
+    hwcap: 0,
+    hwcap2: 0,
+    max_cpuid: 0,
+    family: 0,
+    model: 0,
+    stepping: 0,
+    cpuid: [EMPTY; CPUID_INDEX_MAX],
+    xcr0: 0,
+};
+
+#[inline(always)]
+pub unsafe fn cpuid(leaf: u32, subleaf: u32) -> Cpuid {
+    let (eax, ebx, ecx, edx): (u32, u32, u32, u32);
+    // %rbx is reserved, so it's swapped out through %rsi
+    asm!("movq %rbx, %rsi
+          cpuid
+          xchgq %rbx, %rsi"
+         : "={eax}"(eax), "={esi}"(ebx), "={ecx}"(ecx), "={edx}"(edx)
+         : "{eax}"(leaf), "{ecx}"(subleaf)
+         :
+         : "volatile"
+         );
+    Cpuid { eax: eax, ebx: ebx, ecx: ecx, edx: edx }
+}
+
+#[inline(always)]
+unsafe fn xgetbv(xcr: u32) -> u64 {
+    let (lo, hi): (u32, u32);
+    asm!("xgetbv"
+         : "={eax}"(lo), "={edx}"(hi)
+         : "{ecx}"(xcr)
+         :
+         : "volatile"
+         );
+    (hi as u64) << 32 | lo as u64
+}
+
+/// Decodes the family, model and stepping from leaf 1's `%eax`; the extended family only counts for family 0xf, and the extended model for families 6 and 0xf
+fn signature(eax: u32) -> (u32, u32, u32) {
+    let stepping = eax & 0xf;
+    let mut model = (eax >> 4) & 0xf;
+    let mut family = (eax >> 8) & 0xf;
+    if family == 0xf {
+        family += (eax >> 20) & 0xff;
+    }
+    if family == 0x6 || family >= 0xf {
+        model += ((eax >> 16) & 0xf) << 4;
+    }
+    (family, model, stepping)
+}
+
+/// Reads the CPU's features; must be called before any IFUNC resolver runs
+pub unsafe fn init(hwcap: u64, hwcap2: u64) {
+    let max_cpuid = cpuid(0, 0).eax;
+    let mut leaves = [EMPTY; CPUID_INDEX_MAX];
+    if max_cpuid >= 1 {
+        leaves[CPUID_INDEX_1] = cpuid(1, 0);
+    }
+    if max_cpuid >= 7 {
+        leaves[CPUID_INDEX_7] = cpuid(7, 0);
+    }
+    if cpuid(0x80000000, 0).eax >= 0x80000001 {
+        leaves[CPUID_INDEX_80000001] = cpuid(0x80000001, 0);
+    }
+    let (family, model, stepping) = signature(leaves[CPUID_INDEX_1].eax);
+    let mut features = CpuFeatures {
+        size: ::std::mem::size_of::<CpuFeatures>() as u64,
+        hwcap: hwcap,
+        hwcap2: hwcap2,
+        max_cpuid: max_cpuid,
+        family: family,
+        model: model,
+        stepping: stepping,
+        cpuid: leaves,
+        xcr0: 0,
+    };
+    if features.has(OSXSAVE) {
+        features.xcr0 = xgetbv(0);
+        if max_cpuid >= 0xd {
+            DRYAD_XSAVE_SIZE = cpuid(0xd, 0).ebx as u64;
+        }
+    }
+    FEATURES = features;
+}
+
+/// The features `init` read
+pub fn get() -> &'static CpuFeatures {
+    unsafe { &FEATURES }
+}
+
+impl CpuFeatures {
+    /// Whether the CPU has the feature
+    #[inline]
+    pub fn has(&self, feature: Feature) -> bool {
+        self.cpuid[feature.index].get(feature.register) & (1 << feature.bit) != 0
+    }
+
+    /// Whether the CPU has the feature, and the kernel saves the register state it needs
+    #[inline]
+    pub fn usable(&self, feature: Feature) -> bool {
+        self.has(feature) && self.xcr0 & feature.xstate == feature.xstate
+    }
+}
+
+#[test]
+fn signature_t() {
+    // a Skylake, an AMD Zen 2, and a Pentium 4
+    assert_eq!(signature(0x000506e3), (0x6, 0x5e, 0x3));
+    assert_eq!(signature(0x00870f10), (0x17, 0x71, 0x0));
+    assert_eq!(signature(0x00000f29), (0xf, 0x2, 0x9));
+    let mut features = CpuFeatures { size: 0, hwcap: 0, hwcap2: 0, max_cpuid: 7, family: 6, model: 0x5e, stepping: 3, cpuid: [EMPTY; CPUID_INDEX_MAX], xcr0: XSTATE_SSE };
+    features.cpuid[CPUID_INDEX_7].ebx = 1 << 5;
+    assert!(features.has(AVX2) && !features.usable(AVX2) && !features.has(SSE2));
+    features.xcr0 |= XSTATE_YMM;
+    assert!(features.usable(AVX2));
+}
@@ -0,0 +1 @@
+pub mod cpu_features;
@@ -0,0 +1,116 @@
+/// The GNU hash table, `DT_GNU_HASH`, which replaces the System V `DT_HASH` table with a better hash function, and a bloom filter so that most lookups for symbols an object _doesn't_ define never touch its chains or string table.
+/// See https://blogs.oracle.com/ali/entry/gnu_hash_elf_sections for the gory details; the in-memory layout is:
+///
+/// ```text
+/// nbuckets: u32
+/// symndx: u32     -- index of the first dynamic symbol reachable through the table
+/// maskwords: u32  -- number of (64-bit) words in the bloom filter, always a power of 2
+/// shift2: u32     -- shift count for the bloom filter's second hash
+/// bloom: [u64; maskwords]
+/// buckets: [u32; nbuckets]
+/// chains: [u32; nsyms - symndx]
+/// ```
+
+use std::fmt;
+use std::slice;
+
+use binary::elf::sym;
+use binary::elf::sym::Sym;
+use binary::elf::strtab::Strtab;
+
+/// Number of bits in a bloom filter word for an ELFCLASS64 object
+const BLOOM_BITS: u32 = 64;
+
+/// The GNU hash function, i.e., Bernstein's `h * 33 + c`
+#[inline]
+pub fn hash(symbol: &str) -> u32 {
+    let mut h: u32 = 5381;
+    for c in symbol.bytes() {
+        h = h.wrapping_mul(33).wrapping_add(c as u32);
+    }
+    h
+}
+
+#[test]
+fn hash_t() {
+    assert_eq!(hash(""), 0x00001505);
+    assert_eq!(hash("printf"), 0x156b2bb8);
+    assert_eq!(hash("exit"), 0x7c967e3f);
+    assert_eq!(hash("syscall"), 0xbac212a0);
+}
+
+pub struct GnuHash<'mmap> {
+    pub nbuckets: u32,
+    pub symndx: u32,
+    pub maskwords: u32,
+    pub shift2: u32,
+    pub bloom: &'mmap [u64],
+    pub buckets: &'mmap [u32],
+    /// The length of the chains isn't stored anywhere, so they're walked until an entry with the low bit set terminates the chain
+    pub chains: *const u32,
+}
+
+impl<'mmap> GnuHash<'mmap> {
+    /// Assumes `addr` is the in-memory (i.e., biased) address of a valid `DT_GNU_HASH` table
+    pub unsafe fn new(addr: u64) -> GnuHash<'mmap> {
+        let header = addr as *const u32;
+        let nbuckets = *header;
+        let symndx = *header.offset(1);
+        let maskwords = *header.offset(2);
+        let shift2 = *header.offset(3);
+        let bloomp = header.offset(4) as *const u64;
+        let bucketsp = bloomp.offset(maskwords as isize) as *const u32;
+        let chains = bucketsp.offset(nbuckets as isize);
+        GnuHash {
+            nbuckets: nbuckets,
+            symndx: symndx,
+            maskwords: maskwords,
+            shift2: shift2,
+            bloom: slice::from_raw_parts(bloomp, maskwords as usize),
+            buckets: slice::from_raw_parts(bucketsp, nbuckets as usize),
+            chains: chains,
+        }
+    }
+
+    /// Finds the symbol named `name`, whose gnu hash is `hash`, in `symtab`, and whose index `accept`s (e.g., it's the right version); the bloom filter lets us bail out early for the vast majority of objects which don't define it
+    pub fn find<'a> (&self, name: &str, hash: u32, symtab: &'a [Sym], strtab: &Strtab, accept: &Fn(usize) -> bool) -> Option<(usize, &'a Sym)> {
+        if self.nbuckets == 0 || self.maskwords == 0 {
+            return None
+        }
+
+        let word = self.bloom[((hash / BLOOM_BITS) % self.maskwords) as usize];
+        let mask = (1u64 << (hash % BLOOM_BITS)) | (1u64 << ((hash >> self.shift2) % BLOOM_BITS));
+        if word & mask != mask {
+            return None
+        }
+
+        let mut idx = self.buckets[(hash % self.nbuckets) as usize];
+        // an empty bucket is 0, which is also always less than symndx
+        if idx < self.symndx {
+            return None
+        }
+
+        loop {
+            let chain_hash = unsafe { *self.chains.offset((idx - self.symndx) as isize) };
+            // the low bit of a chain entry is the end-of-chain marker, and not part of the hash
+            if (hash | 1) == (chain_hash | 1) {
+                if let Some(sym) = symtab.get(idx as usize) {
+                    if sym::is_exported(sym) && &strtab[sym.st_name as usize] == name && accept(idx as usize) {
+                        return Some ((idx as usize, sym))
+                    }
+                }
+            }
+            if chain_hash & 1 == 1 {
+                return None
+            }
+            idx += 1;
+        }
+    }
+}
+
+impl<'mmap> fmt::Debug for GnuHash<'mmap> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "nbuckets: {} symndx: {} maskwords: {} shift2: {}",
+               self.nbuckets, self.symndx, self.maskwords, self.shift2)
+    }
+}
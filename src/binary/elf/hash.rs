@@ -0,0 +1,125 @@
+/// The System V hash table, `DT_HASH`, and how many dynamic symbols an object really has.
+/// The in-memory layout of the table is:
+///
+/// ```text
+/// nbucket: u32
+/// nchain: u32     -- always equal to the number of symbols in the dynamic symbol table
+/// buckets: [u32; nbucket]
+/// chains: [u32; nchain]
+/// ```
+///
+/// The dynamic array doesn't record the size of the dynamic symbol table, so `nchain` is the only place an object tells us;
+/// objects with only a `DT_GNU_HASH` table don't tell us at all, and the highest symbol index has to be recovered by walking the gnu hash chains.
+
+use std::fmt;
+use std::slice;
+
+use binary::elf::sym;
+use binary::elf::sym::Sym;
+use binary::elf::strtab::Strtab;
+use binary::elf::gnu_hash::GnuHash;
+use binary::elf::image::LinkInfo;
+
+/// The System V ELF hash function
+#[inline]
+pub fn hash(symbol: &str) -> u32 {
+    let mut h: u32 = 0;
+    for c in symbol.bytes() {
+        h = (h << 4).wrapping_add(c as u32);
+        let g = h & 0xf0000000;
+        if g != 0 {
+            h ^= g >> 24;
+        }
+        h &= !g;
+    }
+    h
+}
+
+#[test]
+fn hash_t() {
+    assert_eq!(hash(""), 0);
+    assert_eq!(hash("printf"), 0x077905a6);
+    assert_eq!(hash("exit"), 0x0006cf04);
+    assert_eq!(hash("syscall"), 0x0b09985c);
+}
+
+pub struct SysvHash<'mmap> {
+    pub nbucket: u32,
+    pub nchain: u32,
+    pub buckets: &'mmap [u32],
+    pub chains: &'mmap [u32],
+}
+
+impl<'mmap> SysvHash<'mmap> {
+    /// Assumes `addr` is the in-memory (i.e., biased) address of a valid `DT_HASH` table
+    pub unsafe fn new(addr: u64) -> SysvHash<'mmap> {
+        let header = addr as *const u32;
+        let nbucket = *header;
+        let nchain = *header.offset(1);
+        let bucketsp = header.offset(2);
+        let chainsp = bucketsp.offset(nbucket as isize);
+        SysvHash {
+            nbucket: nbucket,
+            nchain: nchain,
+            buckets: slice::from_raw_parts(bucketsp, nbucket as usize),
+            chains: slice::from_raw_parts(chainsp, nchain as usize),
+        }
+    }
+
+    /// Finds the symbol named `name`, whose System V hash is `hash`, in `symtab`, and whose index `accept`s (e.g., it's the right version)
+    pub fn find<'a> (&self, name: &str, hash: u32, symtab: &'a [Sym], strtab: &Strtab, accept: &Fn(usize) -> bool) -> Option<(usize, &'a Sym)> {
+        if self.nbucket == 0 {
+            return None
+        }
+        // STN_UNDEF (0) terminates a chain
+        let mut idx = self.buckets[(hash % self.nbucket) as usize] as usize;
+        while idx != 0 && idx < self.chains.len() {
+            if let Some(sym) = symtab.get(idx) {
+                if sym::is_exported(sym) && &strtab[sym.st_name as usize] == name && accept(idx) {
+                    return Some ((idx, sym))
+                }
+            }
+            idx = self.chains[idx] as usize;
+        }
+        None
+    }
+}
+
+impl<'mmap> fmt::Debug for SysvHash<'mmap> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "nbucket: {} nchain: {}", self.nbucket, self.nchain)
+    }
+}
+
+/// Walks the gnu hash chains to find the highest symbol index reachable through the table, which is the last dynamic symbol,
+/// since the symbols not in the table (e.g., imports) are all sorted before `symndx`
+unsafe fn gnu_hash_num_syms(gnu_hash: &GnuHash) -> usize {
+    let mut last = 0;
+    for bucket in gnu_hash.buckets {
+        if *bucket > last {
+            last = *bucket;
+        }
+    }
+    if last < gnu_hash.symndx {
+        // every bucket is empty
+        return gnu_hash.symndx as usize
+    }
+    while *gnu_hash.chains.offset((last - gnu_hash.symndx) as isize) & 1 == 0 {
+        last += 1;
+    }
+    last as usize + 1
+}
+
+/// Returns the number of symbols in the dynamic symbol table described by `link_info`, whose addresses need `bias` added to them to be valid;
+/// prefers `nchain` from `DT_HASH`, then walking the `DT_GNU_HASH` chains, and only when there are no hash tables at all falls back on assuming the string table immediately follows the symbol table
+pub unsafe fn num_syms(link_info: &LinkInfo, bias: u64) -> usize {
+    if link_info.hash != 0 {
+        SysvHash::new(link_info.hash + bias).nchain as usize
+    } else if link_info.gnu_hash != 0 {
+        gnu_hash_num_syms(&GnuHash::new(link_info.gnu_hash + bias))
+    } else if link_info.syment != 0 && link_info.strtab > link_info.symtab {
+        ((link_info.strtab - link_info.symtab) / link_info.syment) as usize
+    } else {
+        0
+    }
+}
@@ -5,6 +5,9 @@ use utils::*;
 
 pub const EHDR_SIZE: usize = 64;
 
+/// The first four bytes of every ELF file, `e_ident[EI_MAG0..EI_MAG3]`
+pub const ELFMAG: &'static [u8; 4] = b"\x7FELF";
+
 pub const ET_NONE: u16 = 0; /* No file type */
 pub const ET_REL: u16 = 1; /* Relocatable file */
 pub const ET_EXEC: u16 = 2; /* Executable file */
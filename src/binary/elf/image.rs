@@ -1,7 +1,7 @@
-/// TODO: decide on whether to support only Rela (probably yes?); have rdr scan binaries to see frequency of rel (no addend) relocation tables
 use std::fmt;
+use std::slice;
 
-//use utils::*;
+use utils::page;
 
 use binary::elf::program_header;
 use binary::elf::program_header::ProgramHeader;
@@ -12,6 +12,13 @@ use binary::elf::sym::Sym;
 use binary::elf::strtab::Strtab;
 use binary::elf::rela;
 use binary::elf::rela::Rela;
+use binary::elf::rel;
+use binary::elf::rel::Rel;
+use binary::elf::gnu_hash;
+use binary::elf::gnu_hash::GnuHash;
+use binary::elf::hash;
+use binary::elf::hash::SysvHash;
+use binary::elf::version::{Version, Versions};
 
 /// Important dynamic LinkInfo generated via a single pass through the _DYNAMIC array
 pub struct LinkInfo {
@@ -19,6 +26,10 @@ pub struct LinkInfo {
     pub relasz: u64, // TODO: make this a usize?
     pub relaent: u64,
     pub relacount: u64,
+    pub rel: u64,
+    pub relsz: u64,
+    pub relent: u64,
+    pub relcount: u64,
     pub gnu_hash: u64,
     pub hash: u64,
     pub strtab: u64,
@@ -32,15 +43,26 @@ pub struct LinkInfo {
     pub verneed: u64,
     pub verneednum: u64,
     pub versym: u64,
+    pub verdef: u64,
+    pub verdefnum: u64,
     pub init: u64,
     pub fini: u64,
     pub init_array: u64,
     pub init_arraysz: usize,
     pub fini_array: u64,
     pub fini_arraysz: usize,
+    pub preinit_array: u64,
+    pub preinit_arraysz: usize,
     pub needed_count: usize,
+    /// Offset of the `DT_SONAME` in the string table, or 0 if there isn't one
+    pub soname: usize,
+    /// Offsets of the `DT_RPATH` and `DT_RUNPATH` in the string table, or 0
+    pub rpath: usize,
+    pub runpath: usize,
     pub flags: u64,
     pub flags_1: u64,
+    /// Whether the object has the (obsolete) `DT_BIND_NOW` entry, which means the same as `DF_BIND_NOW`
+    pub bind_now: bool,
 }
 
 impl LinkInfo {
@@ -49,6 +71,10 @@ impl LinkInfo {
         let mut relasz = 0;
         let mut relaent = 0;
         let mut relacount = 0;
+        let mut rel = 0;
+        let mut relsz = 0;
+        let mut relent = 0;
+        let mut relcount = 0;
         let mut gnu_hash = 0;
         let mut hash = 0;
         let mut strtab = 0;
@@ -62,21 +88,33 @@ impl LinkInfo {
         let mut verneed = 0;
         let mut verneednum = 0;
         let mut versym = 0;
+        let mut verdef = 0;
+        let mut verdefnum = 0;
         let mut init = 0;
         let mut fini = 0;
         let mut init_array = 0;
         let mut init_arraysz = 0;
         let mut fini_array = 0;
         let mut fini_arraysz = 0;
+        let mut preinit_array = 0;
+        let mut preinit_arraysz = 0;
         let mut needed_count = 0;
+        let mut soname = 0;
+        let mut rpath = 0;
+        let mut runpath = 0;
         let mut flags = 0;
         let mut flags_1 = 0;
+        let mut bind_now = false;
         for dyn in dynamic {
             match dyn.d_tag {
                 dyn::DT_RELA => rela = dyn.d_val + bias, // .rela.dyn
                 dyn::DT_RELASZ => relasz = dyn.d_val,
                 dyn::DT_RELAENT => relaent = dyn.d_val,
                 dyn::DT_RELACOUNT => relacount = dyn.d_val,
+                dyn::DT_REL => rel = dyn.d_val + bias, // .rel.dyn
+                dyn::DT_RELSZ => relsz = dyn.d_val,
+                dyn::DT_RELENT => relent = dyn.d_val,
+                dyn::DT_RELCOUNT => relcount = dyn.d_val,
                 dyn::DT_GNU_HASH => gnu_hash = dyn.d_val + bias,
                 dyn::DT_HASH => hash = dyn.d_val + bias,
                 dyn::DT_STRTAB => strtab = dyn.d_val + bias,
@@ -90,15 +128,23 @@ impl LinkInfo {
                 dyn::DT_VERNEED => verneed = dyn.d_val + bias,
                 dyn::DT_VERNEEDNUM => verneednum = dyn.d_val,
                 dyn::DT_VERSYM => versym = dyn.d_val + bias,
+                dyn::DT_VERDEF => verdef = dyn.d_val + bias,
+                dyn::DT_VERDEFNUM => verdefnum = dyn.d_val,
                 dyn::DT_INIT => init = dyn.d_val + bias,
                 dyn::DT_FINI => fini = dyn.d_val + bias,
                 dyn::DT_INIT_ARRAY => init_array = dyn.d_val + bias,
                 dyn::DT_INIT_ARRAYSZ => init_arraysz = dyn.d_val,
                 dyn::DT_FINI_ARRAY => fini_array = dyn.d_val + bias,
                 dyn::DT_FINI_ARRAYSZ => fini_arraysz = dyn.d_val,
+                dyn::DT_PREINIT_ARRAY => preinit_array = dyn.d_val + bias,
+                dyn::DT_PREINIT_ARRAYSZ => preinit_arraysz = dyn.d_val,
                 dyn::DT_NEEDED => needed_count += 1,
+                dyn::DT_SONAME => soname = dyn.d_val as usize,
+                dyn::DT_RPATH => rpath = dyn.d_val as usize,
+                dyn::DT_RUNPATH => runpath = dyn.d_val as usize,
                 dyn::DT_FLAGS => flags = dyn.d_val,
                 dyn::DT_FLAGS_1 => flags_1 = dyn.d_val,
+                dyn::DT_BIND_NOW => bind_now = true,
                 _ => ()
             }
         }
@@ -108,6 +154,10 @@ impl LinkInfo {
             relasz: relasz,
             relaent: relaent,
             relacount: relacount,
+            rel: rel,
+            relsz: relsz,
+            relent: relent,
+            relcount: relcount,
             gnu_hash: gnu_hash,
             hash: hash,
             strtab: strtab,
@@ -121,26 +171,38 @@ impl LinkInfo {
             verneed: verneed,
             verneednum: verneednum,
             versym: versym,
+            verdef: verdef,
+            verdefnum: verdefnum,
             init: init,
             fini: fini,
             init_array: init_array,
             init_arraysz: init_arraysz as usize,
             fini_array: fini_array,
             fini_arraysz: fini_arraysz as usize,
+            preinit_array: preinit_array,
+            preinit_arraysz: preinit_arraysz as usize,
             needed_count: needed_count,
+            soname: soname,
+            rpath: rpath,
+            runpath: runpath,
             flags: flags,
             flags_1: flags_1,
+            bind_now: bind_now,
         }
     }
 }
 
 impl fmt::Debug for LinkInfo {
     fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "rela: 0x{:x} relasz: {} relaent: {} relacount: {} gnu_hash: 0x{:x} hash: 0x{:x} strtab: 0x{:x} strsz: {} symtab: 0x{:x} syment: {} pltgot: 0x{:x} pltrelsz: {} pltrel: {} jmprel: 0x{:x} verneed: 0x{:x} verneednum: {} versym: 0x{:x} init: 0x{:x} fini: 0x{:x} needed_count: {}",
+        write!(f, "rela: 0x{:x} relasz: {} relaent: {} relacount: {} rel: 0x{:x} relsz: {} relent: {} relcount: {} gnu_hash: 0x{:x} hash: 0x{:x} strtab: 0x{:x} strsz: {} symtab: 0x{:x} syment: {} pltgot: 0x{:x} pltrelsz: {} pltrel: {} jmprel: 0x{:x} verneed: 0x{:x} verneednum: {} versym: 0x{:x} verdef: 0x{:x} verdefnum: {} init: 0x{:x} fini: 0x{:x} init_array: 0x{:x} init_arraysz: {} fini_array: 0x{:x} fini_arraysz: {} preinit_array: 0x{:x} preinit_arraysz: {} needed_count: {} soname: {} rpath: {} runpath: {} flags: {:#x} flags_1: {:#x} bind_now: {}",
                self.rela,
                self.relasz,
                self.relaent,
                self.relacount,
+               self.rel,
+               self.relsz,
+               self.relent,
+               self.relcount,
                self.gnu_hash,
                self.hash,
                self.strtab,
@@ -154,13 +216,36 @@ impl fmt::Debug for LinkInfo {
                self.verneed,
                self.verneednum,
                self.versym,
+               self.verdef,
+               self.verdefnum,
                self.init,
                self.fini,
+               self.init_array,
+               self.init_arraysz,
+               self.fini_array,
+               self.fini_arraysz,
+               self.preinit_array,
+               self.preinit_arraysz,
                self.needed_count,
+               self.soname,
+               self.rpath,
+               self.runpath,
+               self.flags,
+               self.flags_1,
+               self.bind_now,
                )
     }
 }
 
+/// Returns the PLT relocations at the in-memory address `jmprel`, which are rela or rel depending on `DT_PLTREL`; the other table is empty
+pub unsafe fn get_pltrel<'a>(jmprel: u64, pltrelsz: usize, pltrel: u64) -> (&'a[Rela], &'a[Rel]) {
+    if pltrel == dyn::DT_REL {
+        (&[], rel::get_plt(jmprel, pltrelsz))
+    } else {
+        (rela::get_plt(jmprel, pltrelsz), &[])
+    }
+}
+
 pub trait Relocatable<'a> {
     fn name(&'a self) -> &'a str;
     fn symtab(&self) -> &'a[Sym];
@@ -213,8 +298,8 @@ impl<'process> Executable<'process> {
                 let link_info = LinkInfo::new(dynamic, load_bias);
                 let libs = dyn::get_needed(dynamic, load_bias, link_info.strtab, link_info.needed_count);
 
-                // TODO: swap out the link_info syment with compile time constant SIZEOF_SYM?
-                let num_syms = ((link_info.strtab - link_info.symtab) / link_info.syment) as usize; // this _CAN'T_ generally be valid; but rdr has been doing it and scans every linux shared object binary without issue... so it must be right!
+                // the link_info addresses are already biased
+                let num_syms = hash::num_syms(&link_info, 0);
                 let symtab = sym::get_symtab(link_info.symtab as *const sym::Sym, num_syms);
                 let strtab = Strtab::new(link_info.strtab as *const u8, link_info.strsz);
                 let relatab = rela::get(link_info.rela, link_info.relasz as usize, link_info.relaent as usize, link_info.relacount as usize);
@@ -289,7 +374,42 @@ pub struct SharedObject<'mmap> {
     pub symtab: &'mmap[Sym],
     pub relatab: &'mmap[Rela],
     pub pltrelatab: &'mmap[Rela],
+    /// The `DT_REL` table, and the PLT relocations if `DT_PLTREL` is `DT_REL`, in which case `pltrelatab` is empty
+    pub reltab: &'mmap[Rel],
+    pub pltreltab: &'mmap[Rel],
     pub pltgot: *const u64,
+    pub gnu_hash: Option<GnuHash<'mmap>>,
+    pub hash: Option<SysvHash<'mmap>>,
+    /// The versions of the object's symbols, and of the symbols it needs
+    pub versions: Versions<'mmap>,
+    pub flags: u64,
+    pub flags_1: u64,
+    /// The object's dynamic info, with every address already biased
+    pub link_info: LinkInfo,
+    /// The object's `DT_SONAME` if it has one, otherwise the name it was loaded as
+    pub soname: String,
+    /// The path the object was opened from, which is where its `$ORIGIN` is
+    pub path: String,
+    /// The unexpanded `DT_RPATH` and `DT_RUNPATH`
+    pub rpath: Option<String>,
+    pub runpath: Option<String>,
+    /// The device and inode of the file the object was mapped from, so the same file reached through two different names is only loaded once; both are 0 for the executable
+    pub dev: u64,
+    pub ino: u64,
+    /// The link map indices of the objects satisfying each of `libs`, in the same order
+    pub needed: Vec<usize>,
+    /// The link map index of the object which loaded this one, i.e., whose `DT_NEEDED` or `dlopen` asked for it first; `None` for the executable and dryad
+    pub loader: Option<usize>,
+    /// The object's local scope: itself, then its dependencies, breadth first, as link map indices
+    pub scope: Vec<usize>,
+    /// The link map indices of the objects outside its local scope which it bound symbols to, e.g., ones `dlopen`ed `RTLD_GLOBAL` after it; it holds a reference to each, like to its dependencies
+    pub reldeps: Vec<usize>,
+    /// Whether the object is in the global scope, i.e., it was loaded at startup, or `dlopen`ed with `RTLD_GLOBAL`; otherwise only the objects it's in the local scope of see its definitions
+    pub global: bool,
+    /// How many `dlopen`s and dependents are holding the object; it's unloaded when `dlclose` drops this to 0, unless it's `DF_1_NODELETE`
+    pub refcount: usize,
+    /// Whether `dlclose` unloaded the object; it keeps its slot in the link map so no other index changes, but nothing it pointed at is mapped anymore
+    pub unloaded: bool,
 }
 
 impl<'process> SharedObject<'process> {
@@ -309,20 +429,33 @@ impl<'process> SharedObject<'process> {
                 }
             }
             // if base == 0 then no PT_PHDR and we should terminate? or kernel should have noticed this and we needn't bother
+            SharedObject::from_memory(name, load_bias, phdrs)
+        }
+    }
 
+    /// Builds the object from an image that's already mapped at `load_bias`, e.g., the executable, which the kernel mapped, or dryad itself
+    pub fn from_memory (name: &'static str, load_bias: u64, phdrs: &'process [ProgramHeader]) -> Result<SharedObject<'process>, String> {
+        unsafe {
             if let Some(dynamic) = dyn::get_dynamic_array(load_bias, phdrs) {
 
                 let link_info = LinkInfo::new(dynamic, load_bias);
                 let libs = dyn::get_needed(dynamic, load_bias, link_info.strtab, link_info.needed_count);
 
-                // TODO: swap out the link_info syment with compile time constant SIZEOF_SYM?
-                let num_syms = ((link_info.strtab - link_info.symtab) / link_info.syment) as usize; // this _CAN'T_ generally be valid; but rdr has been doing it and scans every linux shared object binary without issue... so it must be right!
+                // the link_info addresses are already biased
+                let num_syms = hash::num_syms(&link_info, 0);
                 let symtab = sym::get_symtab(link_info.symtab as *const sym::Sym, num_syms);
                 let strtab = Strtab::new(link_info.strtab as *const u8, link_info.strsz);
                 let relatab = rela::get(link_info.rela, link_info.relasz as usize, link_info.relaent as usize, link_info.relacount as usize);
-                let pltrelatab = rela::get_plt(link_info.jmprel, link_info.pltrelsz as usize);
+                let reltab = rel::get(link_info.rel, link_info.relsz as usize, link_info.relent as usize);
+                let (pltrelatab, pltreltab) = get_pltrel(link_info.jmprel, link_info.pltrelsz as usize, link_info.pltrel);
 
                 let pltgot = link_info.pltgot as *const u64;
+                let gnu_hash = if link_info.gnu_hash == 0 { None } else { Some (GnuHash::new(link_info.gnu_hash)) };
+                let hash = if link_info.hash == 0 { None } else { Some (SysvHash::new(link_info.hash)) };
+                let soname = if link_info.soname == 0 { name.to_string() } else { strtab[link_info.soname].to_string() };
+                let rpath = if link_info.rpath == 0 { None } else { Some (strtab[link_info.rpath].to_string()) };
+                let runpath = if link_info.runpath == 0 { None } else { Some (strtab[link_info.runpath].to_string()) };
+                let versions = Versions::new(link_info.versym, num_syms, link_info.verdef, link_info.verdefnum as usize, link_info.verneed, link_info.verneednum as usize, &strtab);
 
                 Ok (SharedObject {
                     name: name.to_string(),
@@ -336,21 +469,104 @@ impl<'process> SharedObject<'process> {
                     strtab: strtab,
                     relatab: relatab,
                     pltrelatab: pltrelatab,
+                    reltab: reltab,
+                    pltreltab: pltreltab,
                     pltgot: pltgot,
+                    gnu_hash: gnu_hash,
+                    hash: hash,
+                    versions: versions,
+                    flags: link_info.flags,
+                    flags_1: link_info.flags_1,
+                    soname: soname,
+                    path: name.to_string(),
+                    rpath: rpath,
+                    runpath: runpath,
+                    link_info: link_info,
+                    dev: 0,
+                    ino: 0,
+                    needed: Vec::new(),
+                    loader: None,
+                    scope: Vec::new(),
+                    reldeps: Vec::new(),
+                    global: false,
+                    refcount: 0,
+                    unloaded: false,
                 })
 
             } else {
 
-                Err (format!("<dryad> Error: executable {} has no _DYNAMIC array", name))
+                Err (format!("<dryad> Error: {} has no _DYNAMIC array", name))
             }
         }
     }
 
+    /// Whether `addr` is inside one of the object's `PT_LOAD` segments
+    pub fn contains (&self, addr: u64) -> bool {
+        self.phdrs.iter().any(|phdr| {
+            let start = phdr.p_vaddr + self.load_bias;
+            phdr.p_type == program_header::PT_LOAD && addr >= start && addr < start + phdr.p_memsz
+        })
+    }
+
+    /// Finds the symbol named `symbol` this object defines, given its precomputed gnu `hash`, which satisfies a reference asking for `version` (or for no version at all);
+    /// uses the `DT_GNU_HASH` table if the object has one, then the System V `DT_HASH` table, and otherwise falls back on a linear scan of the symbol table
+    pub fn find_sym (&self, symbol: &str, hash: u32, version: Option<&Version>) -> Option<&'process Sym> {
+        let accept = |idx: usize| self.versions.matches(idx, version);
+        if let Some(ref gnu_hash) = self.gnu_hash {
+            return gnu_hash.find(symbol, hash, self.symtab, &self.strtab, &accept).map(|(_, sym)| sym)
+        }
+        if let Some(ref sysv_hash) = self.hash {
+            return sysv_hash.find(symbol, hash::hash(symbol), self.symtab, &self.strtab, &accept).map(|(_, sym)| sym)
+        }
+        for (idx, sym) in self.symtab.iter().enumerate() {
+            if sym::is_exported(&sym) &&
+                &self.strtab[sym.st_name as usize] == symbol && accept(idx) {
+                return Some (sym)
+            }
+        }
+        None
+    }
+
+    /// Returns the address of `symbol` if this object defines it in `version`, when the caller has already computed its gnu `hash`, e.g., because it's searching many objects
+    pub fn find_hashed (&self, symbol: &str, hash: u32, version: Option<&Version>) -> Option<u64> {
+        self.find_sym(symbol, hash, version).map(|sym| sym.st_value + self.load_bias)
+    }
+
+    /// Returns the address of the default version of `symbol`, if this object defines it
     pub fn find (&self, symbol: &str) -> Option<u64> {
-        for sym in self.symtab {
-            if !sym::is_import(&sym) &&
-                &self.strtab[sym.st_name as usize] == symbol {
-                return Some (sym.st_value + self.load_bias)
+        self.find_hashed(symbol, gnu_hash::hash(symbol), None)
+    }
+
+    /// Returns the `DT_PREINIT_ARRAY` function pointers; only an executable is allowed to have these
+    pub fn preinit_array (&self) -> &'process [u64] {
+        unsafe { slice::from_raw_parts(self.link_info.preinit_array as *const u64, self.link_info.preinit_arraysz / 8) }
+    }
+
+    /// Returns the `DT_INIT_ARRAY` function pointers, in the order they're called
+    pub fn init_array (&self) -> &'process [u64] {
+        unsafe { slice::from_raw_parts(self.link_info.init_array as *const u64, self.link_info.init_arraysz / 8) }
+    }
+
+    /// Returns the `DT_FINI_ARRAY` function pointers; these are called in _reverse_ order
+    pub fn fini_array (&self) -> &'process [u64] {
+        unsafe { slice::from_raw_parts(self.link_info.fini_array as *const u64, self.link_info.fini_arraysz / 8) }
+    }
+
+    /// Whether the object was linked with `-z now`, i.e., it has `DF_BIND_NOW`, `DF_1_NOW` or `DT_BIND_NOW`, in which case its entire GOT, including the PLT slots, is usually covered by its `PT_GNU_RELRO` segment, and so can't be bound lazily
+    pub fn is_bind_now (&self) -> bool {
+        self.flags & dyn::DF_BIND_NOW != 0 || self.flags_1 & dyn::DF_1_NOW != 0 || self.link_info.bind_now
+    }
+
+    /// Returns the page aligned, in-memory start and length of the object's `PT_GNU_RELRO` segment, if it has one;
+    /// like the other loaders, the end is rounded _down_, since the last page may be shared with data that has to stay writable
+    pub fn relro (&self) -> Option<(u64, usize)> {
+        for phdr in &self.phdrs {
+            if phdr.p_type == program_header::PT_GNU_RELRO {
+                let start = page::page_start(phdr.p_vaddr + self.load_bias);
+                let end = page::page_start(phdr.p_vaddr + phdr.p_memsz + self.load_bias);
+                if end > start {
+                    return Some ((start, (end - start) as usize))
+                }
             }
         }
         None
@@ -360,8 +576,8 @@ impl<'process> SharedObject<'process> {
 
 impl<'mmap> fmt::Debug for SharedObject<'mmap> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "name: {} load_bias: {:x}\n  ProgramHeaders: {:#?}\n  _DYNAMIC: {:#?}\n  String Table: {:#?}\n  Symbol Table: {:#?}\n  Rela Table: {:#?}\n  Plt Rela Table: {:#?}\n  Libraries: {:#?}",
-               self.name, self.load_bias, self.phdrs, self.dynamic, self.strtab, self.symtab, self.relatab, self.pltrelatab, self.libs)
+        write!(f, "name: {} load_bias: {:x}\n  ProgramHeaders: {:#?}\n  _DYNAMIC: {:#?}\n  String Table: {:#?}\n  Symbol Table: {:#?}\n  Rela Table: {:#?}\n  Plt Rela Table: {:#?}\n  Rel Table: {:#?}\n  Plt Rel Table: {:#?}\n  Libraries: {:#?}\n  Gnu Hash: {:#?}\n  Hash: {:#?}\n  Versions: {:#?}",
+               self.name, self.load_bias, self.phdrs, self.dynamic, self.strtab, self.symtab, self.relatab, self.pltrelatab, self.reltab, self.pltreltab, self.libs, self.gnu_hash, self.hash, self.versions)
     }
 }
 
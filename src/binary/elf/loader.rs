@@ -3,21 +3,29 @@
 
 use std::fs::File;
 use std::io::Read;
-//use std::io::Seek;
-//use std::io::SeekFrom::{ Start };
+use std::io::Seek;
+use std::io::SeekFrom::{ Start };
 use std::os::unix::io::AsRawFd;
 use std::slice;
+use std::ptr;
 //use std::mem;
 use std::os::raw::{c_int};
 
 use utils::mmap;
 use utils::page;
+use utils::debug;
 use binary::elf::header;
 use binary::elf::program_header;
 use binary::elf::dyn;
 use binary::elf::sym;
 use binary::elf::rela;
+use binary::elf::rel;
 use binary::elf::strtab::Strtab;
+use binary::elf::gnu_hash::GnuHash;
+use binary::elf::hash;
+use binary::elf::hash::SysvHash;
+use binary::elf::version::Versions;
+use binary::elf::image;
 use binary::elf::image::{LinkInfo, SharedObject};
 
 extern {
@@ -25,6 +33,9 @@ extern {
     fn __errno_location() -> *const i32;
 }
 
+/// errno for a `MAP_FIXED_NOREPLACE` range which is already mapped
+const EEXIST: i32 = 17;
+
 #[inline(always)]
 fn get_errno () -> i32 {
     unsafe { *__errno_location() }
@@ -62,10 +73,22 @@ fn map_fragment(fd: &File, base: u64, offset: u64, size: usize) -> Result<(u64,
     }
 }
 
+/// Translates the virtual address `vaddr` into its offset in the file, using the `PT_LOAD` segment which contains it;
+/// for the first segment of a typical shared object the two are the same, but for an `ET_EXEC` they never are
+#[inline(always)]
+fn vaddr_to_offset (phdrs: &[program_header::ProgramHeader], vaddr: u64) -> u64 {
+    for phdr in phdrs {
+        if phdr.p_type == program_header::PT_LOAD && phdr.p_vaddr <= vaddr && vaddr < phdr.p_vaddr + phdr.p_filesz {
+            return vaddr - phdr.p_vaddr + phdr.p_offset
+        }
+    }
+    vaddr
+}
+
 #[inline(always)]
 fn compute_load_size (phdrs: &[program_header::ProgramHeader]) -> (usize, u64, u64) {
     let mut max_vaddr = 0;
-    let mut min_vaddr = 0;
+    let mut min_vaddr = !0;
     for phdr in phdrs {
 
         if phdr.p_type != program_header::PT_LOAD {
@@ -87,31 +110,67 @@ fn compute_load_size (phdrs: &[program_header::ProgramHeader]) -> (usize, u64, u
     ((max_vaddr - min_vaddr) as usize, min_vaddr, max_vaddr)
 }
 
+/// The largest `p_align` of the `PT_LOAD` segments, but never less than a page, which is all a `p_align` of 0 or 1 asks for anyway; one which isn't a power of two is bogus, and ignored
+#[inline(always)]
+fn compute_load_align (phdrs: &[program_header::ProgramHeader]) -> u64 {
+    let mut align = page::PAGE_SIZE;
+    for phdr in phdrs {
+        if phdr.p_type == program_header::PT_LOAD && phdr.p_align > align && phdr.p_align.is_power_of_two() {
+            align = phdr.p_align;
+        }
+    }
+    align
+}
+
+/// Reserves, but doesn't make accessible, the address space for all of the `PT_LOAD` segments, with a load bias aligned to the largest `p_align` they ask for, so every segment is as aligned in memory as it was linked;
+/// an `ET_EXEC` has to go exactly where it was linked, so it is `fixed`, and it's an error if anything, e.g., dryad itself or the stack, is already mapped there
 #[inline(always)]
-fn reserve_address_space (phdrs: &[program_header::ProgramHeader]) -> Result <(u64, u64, u64), String> {
+fn reserve_address_space (phdrs: &[program_header::ProgramHeader], fixed: bool) -> Result <(u64, u64, u64), String> {
 
     let (size, min_vaddr, max_vaddr) = compute_load_size(&phdrs);
+    let align = compute_load_align(&phdrs);
 
-    let mmap_flags = mmap::MAP_PRIVATE | mmap::MAP_ANONYMOUS;
-    let start = unsafe { mmap::mmap(0 as *const u64,
-                                    size,
-                                    // TODO: this is _UNSAFE_:
-                                    // for now, using PROT_NONE seems to give SEGV_ACCERR on execution of PT_LOAD mmaped segments (i.e., operation not allowed on mapped object)
-                                    mmap::PROT_EXEC | mmap::PROT_READ | mmap::PROT_WRITE,
-//                                    mmap::PROT_NONE,
-                                    mmap_flags as c_int,
-                                    -1,
-                                    0) };
+    // over-reserve by the alignment, so we can trim it back down to a start with an aligned bias
+    let (hint, reserve_size, mmap_flags) = if fixed {
+        (min_vaddr, size, mmap::MAP_PRIVATE | mmap::MAP_ANONYMOUS | mmap::MAP_FIXED_NOREPLACE)
+    } else {
+        (0, size + (align - page::PAGE_SIZE) as usize, mmap::MAP_PRIVATE | mmap::MAP_ANONYMOUS)
+    };
+    let reserved = unsafe { mmap::mmap(hint as *const u64,
+                                       reserve_size,
+                                       mmap::PROT_NONE,
+                                       mmap_flags as c_int,
+                                       -1,
+                                       0) };
 
-    if start == mmap::MAP_FAILED {
+    // kernels before 4.17 don't know MAP_FIXED_NOREPLACE, and take the address as a hint, which they only honor if it's free
+    if fixed && (reserved == mmap::MAP_FAILED && get_errno() == EEXIST || reserved != mmap::MAP_FAILED && reserved != min_vaddr) {
+        if reserved != mmap::MAP_FAILED {
+            unsafe { mmap::munmap(reserved as *const u64, size); }
+        }
+        Err(format!("<dryad> Error: cannot load the executable at {:#x} - {:#x}, which overlaps an existing mapping, e.g., dryad's own", min_vaddr, max_vaddr))
+
+    } else if reserved == mmap::MAP_FAILED {
 
         Err(format!("<dryad> Failure: anonymous mmap failed for size {:x} with errno {}", size, get_errno()))
 
     } else {
 
-        let load_bias = start - min_vaddr;
+        // both `reserved` and `min_vaddr` are page aligned, so this moves the start up by at most `align - PAGE_SIZE`, which is what we over-reserved by;
+        // the bias wraps if the object was linked above where it ended up
+        let start = if fixed { reserved } else { (reserved.wrapping_sub(min_vaddr).wrapping_add(align - 1) & !(align - 1)).wrapping_add(min_vaddr) };
+        unsafe {
+            if start > reserved {
+                mmap::munmap(reserved as *const u64, (start - reserved) as usize);
+            }
+            let reserved_end = reserved + reserve_size as u64;
+            if reserved_end > start + size as u64 {
+                mmap::munmap((start + size as u64) as *const u64, (reserved_end - (start + size as u64)) as usize);
+            }
+        }
+        let load_bias = start.wrapping_sub(min_vaddr);
         let end = start + size as u64;
-        println!("Reserved {:#x} - {:#x}", start, (start + size as u64));
+        debug!(debug::FILES, "reserved {:#x} - {:#x}", start, (start + size as u64));
 
         Ok((start, load_bias, end))
     }
@@ -156,6 +215,9 @@ pub fn load<'a> (soname: &str, fd: &mut File) -> Result <SharedObject<'a>, Strin
     let _ = fd.read(&mut elf_header);
 
     let elf_header = header::from_bytes(&elf_header);
+    if &elf_header.e_ident[0..4] != header::ELFMAG {
+        return Err(format!("<dryad> Error: {} is not an ELF file", soname))
+    }
     // TODO: phdr should be mmapped and not copied?
     let mut phdrs: Vec<u8> = vec![0; (elf_header.e_phnum as u64 * program_header::PHDR_SIZE) as usize];
     let _ = fd.read(&mut phdrs);
@@ -169,26 +231,19 @@ pub fn load<'a> (soname: &str, fd: &mut File) -> Result <SharedObject<'a>, Strin
     let link_info = LinkInfo::new(&dynamic, 0);
 
     // now get the strtab from the dynamic array
-    let (strtab_start, strtab_size, strtab_data) = try!(map_fragment(&fd, 0, link_info.strtab, link_info.strsz));
+    let (strtab_start, strtab_size, strtab_data) = try!(map_fragment(&fd, 0, vaddr_to_offset(phdrs, link_info.strtab), link_info.strsz));
     let strtab = Strtab::new(strtab_data as *const u8, link_info.strsz as usize);
 
     let needed = dyn::get_needed(dynamic, 0, strtab_data as u64, link_info.needed_count);
 
-    let symtab_ptr = link_info.symtab as *const sym::Sym;
-    // let (symtab_start, symtab_size, symtab_data) = try!(map_fragment(&fd, 0, link_info.symtab, 2192 * sym::SIZEOF_SYM as u64));
-    let (symtab_start, symtab_size, symtab_data) = try!(map_fragment(&fd, 0, link_info.symtab, (link_info.strtab - link_info.symtab) as usize));
-
-    let num_syms = (link_info.strtab - link_info.symtab) / sym::SIZEOF_SYM as u64;
-    // TODO: probably remove this?, and add unsafe
-    let symtab = sym::get_symtab(symtab_data as *const sym::Sym, num_syms as usize);
-
     // 2. Reserve address space with anon mmap
-    let (start, load_bias, end) = try!(reserve_address_space(&phdrs));
+    let (start, load_bias, end) = try!(reserve_address_space(&phdrs, elf_header.e_type == header::ET_EXEC));
 
     // semi-hack with adding the load bias right now, but probably fine
     let relatab = unsafe { rela::get(link_info.rela + load_bias, link_info.relasz as usize, link_info.relaent as usize, link_info.relacount as usize) };
 
-    let pltrelatab = unsafe { rela::get_plt(link_info.jmprel + load_bias, link_info.pltrelsz as usize) };
+    let reltab = if link_info.rel == 0 { &[] } else { unsafe { rel::get(link_info.rel + load_bias, link_info.relsz as usize, link_info.relent as usize) } };
+    let (pltrelatab, pltreltab) = unsafe { image::get_pltrel(link_info.jmprel + load_bias, link_info.pltrelsz as usize, link_info.pltrel) };
 
     // TODO: place this in a separate function
     // 3. mmap the PT_LOAD program headers
@@ -204,9 +259,9 @@ pub fn load<'a> (soname: &str, fd: &mut File) -> Result <SharedObject<'a>, Strin
         let seg_end:u64   = seg_start + phdr.p_memsz;
 
         let seg_page_start:u64 = page::page_start(seg_start);
-        let seg_page_end:u64   = page::page_start(seg_end);
+        let seg_page_end:u64   = page::page_end(seg_end);
 
-        // TODO: figure this out
+        // where the part of the segment backed by the file ends, and the part that must be zero (i.e., the .bss) begins
         let seg_file_end:u64 = seg_start + phdr.p_filesz;
 
         // File offsets.
@@ -218,11 +273,12 @@ pub fn load<'a> (soname: &str, fd: &mut File) -> Result <SharedObject<'a>, Strin
 
         // TODO: add error checking, if file size <= 0, if file_end greater than file_size, etc.
 
-        println!("PT_LOAD:\n\tseg_start: {:x} seg_end: {:x} seg_page_start: {:x} seg_page_end: {:x} seg_file_end: {:x}\n\tfile_start: {:x} file_end: {:x} file_page_start: {:x} file_length: {:x}", seg_start, seg_end, seg_page_start, seg_page_end, seg_file_end, file_start, file_end, file_page_start, file_length);
+        debug!(debug::FILES, "PT_LOAD:\n\tseg_start: {:x} seg_end: {:x} seg_page_start: {:x} seg_page_end: {:x} seg_file_end: {:x}\n\tfile_start: {:x} file_end: {:x} file_page_start: {:x} file_length: {:x}", seg_start, seg_end, seg_page_start, seg_page_end, seg_file_end, file_start, file_end, file_page_start, file_length);
+
+        let prot_flags = pflags_to_prot(phdr.p_flags);
 
-        if file_length != 0 {
+        if phdr.p_filesz != 0 {
             let mmap_flags = mmap::MAP_FIXED | mmap::MAP_PRIVATE;
-            let prot_flags = pflags_to_prot(phdr.p_flags);
             unsafe {
                 let start = mmap::mmap(seg_page_start as *const u64,
                                        file_length as usize,
@@ -236,28 +292,59 @@ pub fn load<'a> (soname: &str, fd: &mut File) -> Result <SharedObject<'a>, Strin
                     return Err(format!("<dryad> loading phdrs for {} failed with errno {}, aborting execution", &soname, get_errno()))
                 }
             }
+
+            // the rest of the last file-backed page is whatever follows the segment in the file, but it's the beginning of the .bss, so it has to be cleared
+            if phdr.p_memsz > phdr.p_filesz && page::page_offset(seg_file_end) != 0 {
+                let zero_len = (page::page_end(seg_file_end) - seg_file_end) as usize;
+                let writable = prot_flags & mmap::PROT_WRITE == mmap::PROT_WRITE;
+                unsafe {
+                    if !writable {
+                        mmap::mprotect(page::page_start(seg_file_end) as *const u64, page::PAGE_SIZE as usize, prot_flags | mmap::PROT_WRITE);
+                    }
+                    ptr::write_bytes(seg_file_end as *mut u8, 0, zero_len);
+                    if !writable {
+                        mmap::mprotect(page::page_start(seg_file_end) as *const u64, page::PAGE_SIZE as usize, prot_flags);
+                    }
+                }
+            }
         }
 
-        // TODO: other more boring shit to do with zero'd out extra pages if too big, etc.
-        //seg_file_end = page::page_end(seg_file_end);
-    }
+        // and whatever .bss is left over past the last file-backed page gets fresh anonymous, and hence zeroed, pages
+        let bss_page_start = if phdr.p_filesz == 0 { seg_page_start } else { page::page_end(seg_file_end) };
+        if seg_page_end > bss_page_start {
+            let mmap_flags = mmap::MAP_FIXED | mmap::MAP_PRIVATE | mmap::MAP_ANONYMOUS;
+            unsafe {
+                let start = mmap::mmap(bss_page_start as *const u64,
+                                       (seg_page_end - bss_page_start) as usize,
+                                       prot_flags,
+                                       mmap_flags as c_int,
+                                       -1,
+                                       0);
 
-    // TODO: i believe we'll move calling the constructors to the relocation phase, once the dependency resolution has run and has flattened the list into a linear depends upon sequence
-    // call constructors:
+                if start == mmap::MAP_FAILED {
 
-    /*
-    if link_info.init != 0 {
-        unsafe {
-            println!("Calling constructor @ {:#x} for {}", link_info.init + load_bias, soname);
-            let init: (fn() -> ()) = mem::transmute::<u64, (fn() -> ())>(link_info.init + load_bias);
-            init();
+                    return Err(format!("<dryad> mapping .bss for {} failed with errno {}, aborting execution", &soname, get_errno()))
+                }
+            }
         }
     }
-    */
+
+    // constructors are run by the linker, in dependency order, once everything is relocated
     //TODO: make this an optional
     let pltgot = if link_info.pltgot == 0 { 0 } else { link_info.pltgot + load_bias }; // musl doesn't have a PLTGOT, for example
 
-    println!("Done");
+    // the symbol and hash tables live in a PT_LOAD segment, so it's safe to read them now that they're mapped
+    let gnu_hash = if link_info.gnu_hash == 0 { None } else { Some (unsafe { GnuHash::new(link_info.gnu_hash + load_bias) }) };
+    let hash = if link_info.hash == 0 { None } else { Some (unsafe { SysvHash::new(link_info.hash + load_bias) }) };
+    let num_syms = unsafe { hash::num_syms(&link_info, load_bias) };
+    let symtab = sym::get_symtab((link_info.symtab + load_bias) as *const sym::Sym, num_syms);
+    let bias = |addr: u64| if addr == 0 { 0 } else { addr + load_bias };
+    let versions = unsafe { Versions::new(bias(link_info.versym), num_syms, bias(link_info.verdef), link_info.verdefnum as usize, bias(link_info.verneed), link_info.verneednum as usize, &strtab) };
+
+
+    let dt_soname = if link_info.soname == 0 { soname.to_string() } else { strtab[link_info.soname].to_string() };
+    let rpath = if link_info.rpath == 0 { None } else { Some (strtab[link_info.rpath].to_string()) };
+    let runpath = if link_info.runpath == 0 { None } else { Some (strtab[link_info.runpath].to_string()) };
 
     let shared_object = SharedObject {
         name: soname.to_string(), // this gets corrupted if we _don't_ mem::forget all of dryad
@@ -273,8 +360,64 @@ pub fn load<'a> (soname: &str, fd: &mut File) -> Result <SharedObject<'a>, Strin
         strtab: strtab,
         relatab: relatab,
         pltrelatab: pltrelatab,
+        reltab: reltab,
+        pltreltab: pltreltab,
         pltgot: pltgot as *const u64,
+        gnu_hash: gnu_hash,
+        hash: hash,
+        versions: versions,
+        flags: link_info.flags,
+        flags_1: link_info.flags_1,
+        link_info: LinkInfo::new(dynamic, load_bias),
+        soname: dt_soname,
+        path: soname.to_string(),
+        rpath: rpath,
+        runpath: runpath,
+        dev: 0,
+        ino: 0,
+        needed: Vec::new(),
+        loader: None,
+        scope: Vec::new(),
+        reldeps: Vec::new(),
+        global: false,
+        refcount: 0,
+        unloaded: false,
     };
 
     Ok (shared_object)
 }
+
+/// Maps the program `name` the way the kernel would have if it had been executed directly, for when dryad is invoked as `dryad.so.1 <program> [args...]`.
+/// Returns the address of the program headers in memory, how many there are, and the entry point, i.e., what the kernel would have put in `AT_PHDR`, `AT_PHNUM` and `AT_ENTRY`
+pub fn load_program (name: &str, fd: &mut File) -> Result <(u64, usize, u64), String> {
+    let mut elf_header = [0; header::EHDR_SIZE];
+    let _ = fd.read(&mut elf_header);
+    let (e_type, e_entry, e_phoff) = {
+        let elf_header = header::from_bytes(&elf_header);
+        (elf_header.e_type, elf_header.e_entry, elf_header.e_phoff)
+    };
+    if e_type != header::ET_EXEC && e_type != header::ET_DYN {
+        return Err(format!("<dryad> Error: {} is not an executable", name))
+    }
+    let _ = fd.seek(Start(0));
+
+    let image = try!(load(name, fd));
+    let mut phdr_addr = 0;
+    // PT_PHDR must precede any loadable segment, but not every program has one, in which case we find the program headers in the segment which maps e_phoff
+    for phdr in &image.phdrs {
+        if phdr.p_type == program_header::PT_PHDR {
+            phdr_addr = phdr.p_vaddr + image.load_bias;
+            break
+        }
+        if phdr.p_type == program_header::PT_LOAD && phdr.p_offset <= e_phoff && e_phoff < phdr.p_offset + phdr.p_filesz {
+            phdr_addr = e_phoff - phdr.p_offset + phdr.p_vaddr + image.load_bias;
+            break
+        }
+    }
+
+    if phdr_addr == 0 {
+        Err(format!("<dryad> Error: program headers of {} are not mapped by any PT_LOAD segment", name))
+    } else {
+        Ok ((phdr_addr, image.phdrs.len(), e_entry + image.load_bias))
+    }
+}
@@ -2,7 +2,11 @@ pub mod header;
 pub mod program_header;
 pub mod dyn;
 pub mod rela;
+pub mod rel;
 pub mod sym;
+pub mod gnu_hash;
+pub mod hash;
+pub mod version;
 pub mod loader;
 pub mod image;
 pub mod link_info;
@@ -0,0 +1,82 @@
+/// `DT_REL` relocations, which are `DT_RELA` relocations without the `r_addend`: the addend is implicit, and is instead whatever the relocation's target already holds.
+/// The types, and their computations, are the same as for rela (see `rela.rs`); x86-64 toolchains almost always emit rela, but nothing stops one from emitting rel, for either `DT_REL` or the PLT (`DT_PLTREL == DT_REL`).
+
+use std::fmt;
+use std::slice;
+
+use binary::elf::rela;
+use binary::elf::rela::Rela;
+
+#[repr(C)]
+pub struct Rel {
+    pub r_offset: u64, /* Address */
+    pub r_info: u64,/* Relocation type and symbol index */
+}
+
+pub const SIZEOF_REL: usize = 8 + 8;
+
+#[inline]
+pub fn r_sym (info: u64) -> u64 {
+    rela::r_sym(info)
+}
+
+#[inline]
+pub fn r_type (info: u64) -> u64 {
+    rela::r_type(info)
+}
+
+impl fmt::Debug for Rel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sym = r_sym(self.r_info);
+        let typ = r_type(self.r_info);
+        write!(f, "r_offset: {:x} {} @ {}",
+               self.r_offset, rela::type_to_str(typ), sym)
+    }
+}
+
+/// Gets the rel entries given the (biased) address of the rel table, its size, and the size of an entry; see `rela::get`
+pub unsafe fn get<'a>(rel: u64, relsz: usize, relent: usize) -> &'a[Rel] {
+    if relent == 0 || rel == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(rel as *const Rel, relsz / relent)
+    }
+}
+
+pub unsafe fn get_plt<'a>(jmprel: u64, pltrelsz: usize) -> &'a[Rel] {
+    slice::from_raw_parts(jmprel as *const Rel, pltrelsz / SIZEOF_REL)
+}
+
+/// Reads the implicit addend of a relocation of type `typ` from its target at `place`: the types with a 32-bit field keep it there, sign extended unless the field is unsigned, and a TLS descriptor's is in its second word; everything else's is the whole word
+pub unsafe fn addend(typ: u64, place: u64) -> i64 {
+    match typ {
+        rela::R_X86_64_NONE | rela::R_X86_64_COPY => 0,
+        rela::R_X86_64_32 | rela::R_X86_64_SIZE32 => *(place as *const u32) as i64,
+        rela::R_X86_64_PC32 | rela::R_X86_64_32S | rela::R_X86_64_DTPOFF32 | rela::R_X86_64_TPOFF32 => *(place as *const i32) as i64,
+        rela::R_X86_64_TLSDESC => *(place as *const i64).offset(1),
+        _ => *(place as *const i64),
+    }
+}
+
+/// Returns the rela equivalent of `rel` in an object loaded at `bias`, with the addend read from its target, so it can be applied exactly like any other rela; the target must be mapped, and not yet relocated
+pub unsafe fn to_rela(rel: &Rel, bias: u64) -> Rela {
+    Rela {
+        r_offset: rel.r_offset,
+        r_info: rel.r_info,
+        r_addend: addend(r_type(rel.r_info), rel.r_offset + bias),
+    }
+}
+
+#[test]
+fn to_rela_t() {
+    let target: [u64; 2] = [0xffff_fff0, 0x20];
+    let place = target.as_ptr() as u64;
+    let rel = Rel { r_offset: 0, r_info: rela::r_info(1, rela::R_X86_64_PC32) };
+    assert_eq!(unsafe { to_rela(&rel, place) }.r_addend, -0x10);
+    let rel = Rel { r_offset: 0, r_info: rela::r_info(1, rela::R_X86_64_32) };
+    assert_eq!(unsafe { to_rela(&rel, place) }.r_addend, 0xffff_fff0);
+    let rel = Rel { r_offset: 0, r_info: rela::r_info(1, rela::R_X86_64_TLSDESC) };
+    assert_eq!(unsafe { to_rela(&rel, place) }.r_addend, 0x20);
+    let rel = Rel { r_offset: 8, r_info: rela::r_info(0, rela::R_X86_64_RELATIVE) };
+    assert_eq!(unsafe { to_rela(&rel, place) }.r_addend, 0x20);
+}
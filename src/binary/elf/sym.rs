@@ -23,12 +23,44 @@ pub fn st_type(info: u8) -> u8 {
     info & 0xf
 }
 
+#[inline]
+pub fn st_visibility(other: u8) -> u8 {
+    other & 0x3
+}
+
+/// Whether `sym` is a reference to a symbol some other object defines, i.e., it's undefined here, whatever its binding
 #[inline(always)]
 pub fn is_import(sym: &Sym) -> bool {
-    let binding = st_bind(sym.st_info);
-    binding == STB_GLOBAL && sym.st_value == 0
+    sym.st_shndx == SHN_UNDEF
+}
+
+/// Whether `sym` is a definition other objects can bind to: it has to have a value, global, weak or unique, default or protected, and a type which can be referenced;
+/// a defined symbol with a value of 0 is only a definition if it's TLS, where it's an offset into the TLS block, and an undefined symbol with a value is a non-PIE executable's canonical PLT entry for a function whose address it takes,
+/// which only satisfies references that aren't calls through a PLT, see `resolve::Class`
+#[inline]
+pub fn is_exported(sym: &Sym) -> bool {
+    if sym.st_value == 0 && (sym.st_shndx == SHN_UNDEF || st_type(sym.st_info) != STT_TLS) {
+        return false
+    }
+    match st_bind(sym.st_info) {
+        STB_GLOBAL | STB_WEAK | STB_GNU_UNIQUE => (),
+        _ => return false,
+    }
+    match st_visibility(sym.st_other) {
+        STV_DEFAULT | STV_PROTECTED => (),
+        _ => return false,
+    }
+    match st_type(sym.st_info) {
+        STT_NOTYPE | STT_OBJECT | STT_FUNC | STT_COMMON | STT_TLS | STT_GNU_IFUNC => true,
+        _ => false,
+    }
 }
 
+// special section indices
+pub const SHN_UNDEF:u16 = 0;/* Undefined section */
+pub const SHN_ABS:u16 = 0xfff1;/* Associated symbol is absolute */
+pub const SHN_COMMON:u16 = 0xfff2;/* Associated symbol is common */
+
 // sym bindings
 pub const STB_LOCAL:u8 = 0;/* Local symbol */
 pub const STB_GLOBAL:u8 = 1;/* Global symbol */
@@ -67,6 +99,23 @@ pub const STT_HIOS:u8 = 12;/* End of OS-specific */
 pub const STT_LOPROC:u8 = 13;/* Start of processor-specific */
 pub const STT_HIPROC:u8 = 15;/* End of processor-specific */
 
+// sym visibilities
+pub const STV_DEFAULT:u8 = 0;/* Default symbol visibility rules */
+pub const STV_INTERNAL:u8 = 1;/* Processor specific hidden class */
+pub const STV_HIDDEN:u8 = 2;/* Sym unavailable in other modules */
+pub const STV_PROTECTED:u8 = 3;/* Not preemptible, not exported */
+
+#[inline]
+pub fn visibility_to_str(vis: u8) -> &'static str {
+    match vis {
+        STV_DEFAULT => "DEFAULT",
+        STV_INTERNAL => "INTERNAL",
+        STV_HIDDEN => "HIDDEN",
+        STV_PROTECTED => "PROTECTED",
+        _ => "UNKNOWN_STV"
+    }
+}
+
 #[inline]
 pub fn type_to_str(typ: u8) -> &'static str {
     match typ {
@@ -88,8 +137,8 @@ impl fmt::Debug for Sym {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let bind = st_bind(self.st_info);
         let typ = st_type(self.st_info);
-        write!(f, "st_name: {} {} {} st_other: {} st_shndx: {} st_value: {:x} st_size: {}",
-               self.st_name, bind_to_str(bind), type_to_str(typ), self.st_other, self.st_shndx, self.st_value, self.st_size)
+        write!(f, "st_name: {} {} {} {} st_shndx: {} st_value: {:x} st_size: {}",
+               self.st_name, bind_to_str(bind), type_to_str(typ), visibility_to_str(st_visibility(self.st_other)), self.st_shndx, self.st_value, self.st_size)
     }
 }
 
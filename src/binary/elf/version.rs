@@ -0,0 +1,247 @@
+/// GNU symbol versioning: `DT_VERSYM`, `DT_VERDEF` and `DT_VERNEED`.
+/// Every dynamic symbol has a `u16` entry in the versym table, parallel to the symbol table, which is an index into the object's versions;
+/// the versions an object defines are in its verdefs, and the versions it needs from each of its dependencies are in its verneeds, and both share the same index space.
+///
+/// ```text
+/// versym: [u16; nsyms]          -- the low 15 bits are the index, and the high bit hides the symbol from unversioned references
+/// verdef: { vd_version: u16, vd_flags: u16, vd_ndx: u16, vd_cnt: u16, vd_hash: u32, vd_aux: u32, vd_next: u32 }
+///         -> verdaux: { vda_name: u32, vda_next: u32 }, the first of which is the version's name
+/// verneed: { vn_version: u16, vn_cnt: u16, vn_file: u32, vn_aux: u32, vn_next: u32 }
+///         -> vernaux: { vna_hash: u32, vna_flags: u16, vna_other: u16, vna_name: u32, vna_next: u32 }, one per version needed from `vn_file`
+/// ```
+///
+/// All of the `_aux` and `_next` fields are byte offsets from the start of the structure they're in, and a `_next` of 0 ends the list.
+/// A symbol whose version is hidden, i.e., `foo@VERS` rather than the default `foo@@VERS`, can only be bound by a reference which asks for exactly that version.
+
+use std::fmt;
+use std::slice;
+
+use binary::elf::strtab::Strtab;
+
+/// The symbol is local, and not available outside the object
+pub const VER_NDX_LOCAL: u16 = 0;
+/// The symbol is global and unversioned; also the index of the object's base version definition
+pub const VER_NDX_GLOBAL: u16 = 1;
+/// Masks out the hidden bit of a versym entry
+pub const VERSYM_VERSION: u16 = 0x7fff;
+/// The symbol is only visible to references asking for its exact version
+pub const VERSYM_HIDDEN: u16 = 0x8000;
+
+/// The verdef is the version definition of the file itself, i.e., its soname
+pub const VER_FLG_BASE: u16 = 0x1;
+/// The version reference is weak, so it's not an error if the version is missing
+pub const VER_FLG_WEAK: u16 = 0x2;
+
+#[repr(C)]
+pub struct Verdef {
+    pub vd_version: u16,
+    pub vd_flags: u16,
+    pub vd_ndx: u16,
+    pub vd_cnt: u16,
+    pub vd_hash: u32,
+    pub vd_aux: u32,
+    pub vd_next: u32,
+}
+
+#[repr(C)]
+pub struct Verdaux {
+    pub vda_name: u32,
+    pub vda_next: u32,
+}
+
+#[repr(C)]
+pub struct Verneed {
+    pub vn_version: u16,
+    pub vn_cnt: u16,
+    pub vn_file: u32,
+    pub vn_aux: u32,
+    pub vn_next: u32,
+}
+
+#[repr(C)]
+pub struct Vernaux {
+    pub vna_hash: u32,
+    pub vna_flags: u16,
+    pub vna_other: u16,
+    pub vna_name: u32,
+    pub vna_next: u32,
+}
+
+/// A version an object either defines, or needs from another object
+pub struct Version {
+    pub name: String,
+    /// The System V hash of `name`
+    pub hash: u32,
+    pub flags: u16,
+    /// The soname of the object this version is needed from, or `None` if the object defines it
+    pub file: Option<String>,
+}
+
+impl fmt::Debug for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.file {
+            Some (ref file) => write!(f, "{} ({}) flags: {:#x}", self.name, file, self.flags),
+            None => write!(f, "{} flags: {:#x}", self.name, self.flags),
+        }
+    }
+}
+
+/// An object's versym table, and its versions indexed the same way
+pub struct Versions<'mmap> {
+    pub versym: &'mmap [u16],
+    pub versions: Vec<Option<Version>>,
+}
+
+impl<'mmap> Versions<'mmap> {
+    /// An object without any version information; every symbol matches every reference
+    pub fn empty() -> Versions<'mmap> {
+        Versions { versym: &[], versions: Vec::new() }
+    }
+
+    /// Assumes the addresses are the in-memory (i.e., biased) addresses of the tables, or 0 if the object doesn't have them, and that `num_syms` is the size of the symbol table
+    pub unsafe fn new(versym: u64, num_syms: usize, verdef: u64, verdefnum: usize, verneed: u64, verneednum: usize, strtab: &Strtab) -> Versions<'mmap> {
+        if versym == 0 {
+            return Versions::empty()
+        }
+
+        let mut versions: Vec<Option<Version>> = Vec::new();
+        {
+            let mut insert = |idx: u16, version: Version| {
+                let idx = (idx & VERSYM_VERSION) as usize;
+                while versions.len() <= idx {
+                    versions.push(None);
+                }
+                versions[idx] = Some (version);
+            };
+
+            let mut def = verdef as *const u8;
+            for _ in 0..(if verdef == 0 { 0 } else { verdefnum }) {
+                let vd = &*(def as *const Verdef);
+                let vda = &*(def.offset(vd.vd_aux as isize) as *const Verdaux);
+                insert(vd.vd_ndx, Version {
+                    name: strtab[vda.vda_name as usize].to_string(),
+                    hash: vd.vd_hash,
+                    flags: vd.vd_flags,
+                    file: None,
+                });
+                if vd.vd_next == 0 {
+                    break
+                }
+                def = def.offset(vd.vd_next as isize);
+            }
+
+            let mut need = verneed as *const u8;
+            for _ in 0..(if verneed == 0 { 0 } else { verneednum }) {
+                let vn = &*(need as *const Verneed);
+                let file = &strtab[vn.vn_file as usize];
+                let mut aux = need.offset(vn.vn_aux as isize);
+                for _ in 0..vn.vn_cnt {
+                    let vna = &*(aux as *const Vernaux);
+                    insert(vna.vna_other, Version {
+                        name: strtab[vna.vna_name as usize].to_string(),
+                        hash: vna.vna_hash,
+                        flags: vna.vna_flags,
+                        file: Some (file.to_string()),
+                    });
+                    if vna.vna_next == 0 {
+                        break
+                    }
+                    aux = aux.offset(vna.vna_next as isize);
+                }
+                if vn.vn_next == 0 {
+                    break
+                }
+                need = need.offset(vn.vn_next as isize);
+            }
+        }
+
+        Versions {
+            versym: slice::from_raw_parts(versym as *const u16, num_syms),
+            versions: versions,
+        }
+    }
+
+    #[inline]
+    fn get(&self, idx: u16) -> Option<&Version> {
+        match self.versions.get(idx as usize) {
+            Some (&Some (ref version)) => Some (version),
+            _ => None,
+        }
+    }
+
+    /// Returns the version the symbol at index `sym` is bound to, e.g., the version a reference needs, or `None` if it's unversioned
+    pub fn version(&self, sym: usize) -> Option<&Version> {
+        match self.versym.get(sym) {
+            Some (&versym) if versym & VERSYM_VERSION > VER_NDX_GLOBAL => self.get(versym & VERSYM_VERSION),
+            _ => None,
+        }
+    }
+
+    /// Whether the definition at index `sym` can satisfy a reference asking for `requested`:
+    /// a versioned reference needs the same version, unless the definition is unversioned; an unversioned reference binds to the default version, and never a hidden one
+    pub fn matches(&self, sym: usize, requested: Option<&Version>) -> bool {
+        let versym = match self.versym.get(sym) {
+            Some (&versym) => versym,
+            None => return true,
+        };
+        let idx = versym & VERSYM_VERSION;
+        let hidden = versym & VERSYM_HIDDEN != 0;
+        if idx == VER_NDX_LOCAL {
+            return false
+        }
+        match (requested, self.get(idx)) {
+            (Some (requested), Some (defined)) if idx > VER_NDX_GLOBAL => defined.hash == requested.hash && defined.name == requested.name,
+            _ => !hidden,
+        }
+    }
+
+    /// Whether this object defines the version `name`
+    pub fn defines(&self, name: &str) -> bool {
+        self.versions.iter().any(|version| match *version {
+            Some (ref version) => version.file.is_none() && version.name == name,
+            None => false,
+        })
+    }
+
+    /// The versions this object needs from its dependencies
+    pub fn needed(&self) -> Vec<&Version> {
+        self.versions.iter().filter_map(|version| match *version {
+            Some (ref version) if version.file.is_some() => Some (version),
+            _ => None,
+        }).collect()
+    }
+
+    /// Whether the object has any version information; if it doesn't, none of its definitions are versioned, and none of its references ask for a version
+    pub fn is_empty(&self) -> bool {
+        self.versym.is_empty()
+    }
+}
+
+impl<'mmap> fmt::Debug for Versions<'mmap> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#?}", self.versions)
+    }
+}
+
+#[test]
+fn matches_t() {
+    fn version(name: &str, file: Option<&str>) -> Option<Version> {
+        Some (Version { name: name.to_string(), hash: ::binary::elf::hash::hash(name), flags: 0, file: file.map(|file| file.to_string()) })
+    }
+    // local, unversioned, foo@@V2, foo@V1, and a reference to V1
+    let versym = [VER_NDX_LOCAL, VER_NDX_GLOBAL, 3, 2 | VERSYM_HIDDEN, 4];
+    let versions = Versions {
+        versym: &versym,
+        versions: vec![None, version("libfoo.so.1", None), version("V1", None), version("V2", None), version("V1", Some ("libfoo.so.1"))],
+    };
+    let v1 = versions.version(4);
+    assert_eq!(v1.map(|v| v.name.as_str()), Some ("V1"));
+    assert!(versions.version(1).is_none());
+    assert!(!versions.matches(0, None));
+    assert!(versions.matches(1, None) && versions.matches(1, v1));
+    assert!(versions.matches(2, None) && !versions.matches(2, v1));
+    assert!(!versions.matches(3, None) && versions.matches(3, v1));
+    assert!(versions.defines("V2") && !versions.defines("V3"));
+    assert_eq!(versions.needed().len(), 1);
+    assert!(Versions::empty().matches(42, v1));
+}
@@ -0,0 +1,210 @@
+/// The `ld.so.cache` which `ldconfig` builds, mapping sonames to the paths of the libraries in every directory it was configured with, so we don't have to know about those directories ourselves.
+/// There are two formats, which `ldconfig` can also write back to back in the same file:
+///
+/// ```text
+/// old, "ld.so-1.7.0":
+///   magic: [u8; 11], padding: u8, nlibs: u32
+///   libs: [{ flags: i32, key: u32, value: u32 }; nlibs]
+///   strings, which the key and value offsets are relative to
+///
+/// new, "glibc-ld.so.cache1.1", 8 byte aligned after the old entries if both are present:
+///   magic: [u8; 17], version: [u8; 3], nlibs: u32, len_strings: u32, unused: [u32; 5]
+///   libs: [{ flags: i32, key: u32, value: u32, osversion: u32, hwcap: u64 }; nlibs]
+///   strings; the key and value offsets are relative to the new header
+/// ```
+///
+/// The key is the soname, and the value its path; the entries for a soname are sorted so the most specific hwcap comes first.
+
+use std::fs::File;
+use std::io::Read;
+use std::str;
+
+/// Where `ldconfig` puts the cache
+pub const CACHE_PATH: &'static str = "/etc/ld.so.cache";
+
+const CACHEMAGIC: &'static [u8] = b"ld.so-1.7.0";
+const CACHEMAGIC_NEW: &'static [u8] = b"glibc-ld.so.cache1.1";
+
+const HEADER_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 12;
+const HEADER_SIZE_NEW: usize = 48;
+const ENTRY_SIZE_NEW: usize = 24;
+
+/// An ELF library for glibc, which is every entry we can use
+pub const FLAG_ELF_LIBC6: i32 = 0x0003;
+/// The library is x86-64, as opposed to i386 or x32
+pub const FLAG_X8664_LIB64: i32 = 0x0300;
+/// The only flags we accept
+pub const FLAG_X8664: i32 = FLAG_ELF_LIBC6 | FLAG_X8664_LIB64;
+
+/// The hwcap bit `ldconfig` uses for libraries in a `tls` subdirectory, which every x86-64 system supports
+pub const HWCAP_TLS: u64 = 1 << 63;
+
+pub struct Entry {
+    pub flags: i32,
+    /// Offsets of the soname and path in the cache
+    key: usize,
+    value: usize,
+    pub hwcap: u64,
+}
+
+pub struct Cache {
+    data: Vec<u8>,
+    entries: Vec<Entry>,
+}
+
+#[inline(always)]
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    (data[offset] as u32) | (data[offset + 1] as u32) << 8 | (data[offset + 2] as u32) << 16 | (data[offset + 3] as u32) << 24
+}
+
+#[inline(always)]
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    (u32_at(data, offset) as u64) | (u32_at(data, offset + 4) as u64) << 32
+}
+
+/// Parses the `nlibs` new format entries in the table whose header begins at `header`
+fn parse_new(data: &[u8], header: usize) -> Result<Vec<Entry>, String> {
+    if data.len() < header + HEADER_SIZE_NEW {
+        return Err(format!("<dryad> ld.so.cache is truncated"))
+    }
+    let nlibs = u32_at(data, header + 20) as usize;
+    let libs = header + HEADER_SIZE_NEW;
+    if data.len() < libs + nlibs * ENTRY_SIZE_NEW {
+        return Err(format!("<dryad> ld.so.cache is truncated, it should have {} entries", nlibs))
+    }
+    let mut entries = Vec::with_capacity(nlibs);
+    for i in 0..nlibs {
+        let entry = libs + i * ENTRY_SIZE_NEW;
+        entries.push(Entry {
+            flags: u32_at(data, entry) as i32,
+            key: header + u32_at(data, entry + 4) as usize,
+            value: header + u32_at(data, entry + 8) as usize,
+            hwcap: u64_at(data, entry + 16),
+        });
+    }
+    Ok (entries)
+}
+
+impl Cache {
+    /// Parses the cache in `data`, preferring the new format's entries when the file has both
+    pub fn new(data: Vec<u8>) -> Result<Cache, String> {
+        let entries = if data.starts_with(CACHEMAGIC) {
+            if data.len() < HEADER_SIZE {
+                return Err(format!("<dryad> ld.so.cache is truncated"))
+            }
+            let nlibs = u32_at(&data, 12) as usize;
+            let strings = HEADER_SIZE + nlibs * ENTRY_SIZE;
+            if data.len() < strings {
+                return Err(format!("<dryad> ld.so.cache is truncated, it should have {} entries", nlibs))
+            }
+            let header_new = (strings + 7) & !7;
+            if header_new <= data.len() && data[header_new..].starts_with(CACHEMAGIC_NEW) {
+                try!(parse_new(&data, header_new))
+            } else {
+                let mut entries = Vec::with_capacity(nlibs);
+                for i in 0..nlibs {
+                    let entry = HEADER_SIZE + i * ENTRY_SIZE;
+                    entries.push(Entry {
+                        flags: u32_at(&data, entry) as i32,
+                        key: strings + u32_at(&data, entry + 4) as usize,
+                        value: strings + u32_at(&data, entry + 8) as usize,
+                        hwcap: 0,
+                    });
+                }
+                entries
+            }
+        } else if data.starts_with(CACHEMAGIC_NEW) {
+            try!(parse_new(&data, 0))
+        } else {
+            return Err(format!("<dryad> ld.so.cache has a bad magic number"))
+        };
+        Ok (Cache { data: data, entries: entries })
+    }
+
+    /// Reads and parses the cache at `path`, which is normally `CACHE_PATH`, but can be any cache `ldconfig -C` built
+    pub fn open(path: &str) -> Result<Cache, String> {
+        match File::open(path) {
+            Ok (mut fd) => {
+                let mut data = Vec::new();
+                if let Err(err) = fd.read_to_end(&mut data) {
+                    return Err(format!("<dryad> could not read {}: {}", path, err))
+                }
+                Cache::new(data)
+            },
+            Err (err) => Err(format!("<dryad> could not open {}: {}", path, err))
+        }
+    }
+
+    /// The NUL terminated string at `offset`, or "" if it's out of bounds or garbage
+    fn str_at(&self, offset: usize) -> &str {
+        if offset >= self.data.len() {
+            return ""
+        }
+        let bytes = &self.data[offset..];
+        let len = bytes.iter().position(|c| *c == 0).unwrap_or(bytes.len());
+        str::from_utf8(&bytes[..len]).unwrap_or("")
+    }
+
+    /// Returns the path of the first x86-64 library named `soname` whose hwcap requirements are all in `hwcap`, i.e., the `AT_HWCAP` the kernel gave us
+    pub fn find(&self, soname: &str, hwcap: u64) -> Option<&str> {
+        let exclude = !(hwcap | HWCAP_TLS);
+        for entry in &self.entries {
+            if entry.flags == FLAG_X8664 && entry.hwcap & exclude == 0 && self.str_at(entry.key) == soname {
+                return Some (self.str_at(entry.value))
+            }
+        }
+        None
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[test]
+fn find_t() {
+    fn push_u32(data: &mut Vec<u8>, x: u32) {
+        for i in 0..4 { data.push((x >> (i * 8)) as u8); }
+    }
+    let strings = b"libfoo.so.1\0/opt/lib/libfoo.so.1\0/opt/lib/x86_64/libfoo.so.1\0/lib32/libfoo.so.1\0";
+    // new format: an entry needing an unsupported hwcap, an i386 one, and the one we want
+    let mut new = Vec::new();
+    new.extend(CACHEMAGIC_NEW);
+    push_u32(&mut new, 3);
+    push_u32(&mut new, strings.len() as u32);
+    for _ in 0..5 { push_u32(&mut new, 0); }
+    for &(flags, value, hwcap) in &[(FLAG_X8664, 33, 1u64 << 40), (FLAG_ELF_LIBC6, 61, 0), (FLAG_X8664, 12, 0)] {
+        push_u32(&mut new, flags as u32);
+        push_u32(&mut new, (HEADER_SIZE_NEW + 3 * ENTRY_SIZE_NEW) as u32);
+        push_u32(&mut new, (HEADER_SIZE_NEW + 3 * ENTRY_SIZE_NEW + value) as u32);
+        push_u32(&mut new, 0);
+        push_u32(&mut new, hwcap as u32);
+        push_u32(&mut new, (hwcap >> 32) as u32);
+    }
+    new.extend(&strings[..]);
+    let cache = Cache::new(new.clone()).unwrap();
+    assert_eq!(cache.len(), 3);
+    assert_eq!(cache.find("libfoo.so.1", 0), Some ("/opt/lib/libfoo.so.1"));
+    assert_eq!(cache.find("libfoo.so.1", 1 << 40), Some ("/opt/lib/x86_64/libfoo.so.1"));
+    assert_eq!(cache.find("libbar.so.1", 0), None);
+
+    // old format only
+    let mut old = Vec::new();
+    old.extend(CACHEMAGIC);
+    old.push(0);
+    push_u32(&mut old, 1);
+    for &x in &[FLAG_X8664 as u32, 0, 12] { push_u32(&mut old, x); }
+    old.extend(&strings[..]);
+    assert_eq!(Cache::new(old.clone()).unwrap().find("libfoo.so.1", 0), Some ("/opt/lib/libfoo.so.1"));
+
+    // both, with the new format hidden in the old format's strings
+    let mut both = old[..HEADER_SIZE + ENTRY_SIZE].to_vec();
+    while both.len() % 8 != 0 { both.push(0); }
+    both.extend(new);
+    let cache = Cache::new(both).unwrap();
+    assert_eq!(cache.len(), 3);
+    assert_eq!(cache.find("libfoo.so.1", 1 << 40), Some ("/opt/lib/x86_64/libfoo.so.1"));
+
+    assert!(Cache::new(b"garbage".to_vec()).is_err());
+}
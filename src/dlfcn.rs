@@ -0,0 +1,212 @@
+/// The `<dlfcn.h>` runtime API: `dlopen`, `dlsym`, `dlclose` and `dlerror`, for programs which load plugins, or look up symbols, once they're running.
+/// These are thin wrappers around the `install`ed `Linker`, which does the loading, binding and relocating exactly as it did at startup; a handle is the `LinkNode` of the object it opened, i.e., whatever its GOT[1] holds.
+///
+/// `dlopen` and `dlsym` are the assembly stubs in `arch/x86/asm.s`, which pass their return address along as the caller, to `dryad_dlopen` and `dryad_dlsym` respectively:
+/// the caller's `DT_RUNPATH` and `$ORIGIN` are what `dlopen` searches, and `RTLD_NEXT` searches the objects after the caller's.
+/// Every failure is recorded for `dlerror`, per thread; since this all runs on the program's TLS, the thread is told apart by its thread pointer, rather than with one of our thread locals
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+
+use linker;
+use linker::LinkNode;
+use tls;
+
+/// Relocations against functions may be bound lazily, i.e., when they're first called
+pub const RTLD_LAZY: c_int = 0x1;
+/// Every relocation is bound before `dlopen` returns, and an undefined symbol is an error
+pub const RTLD_NOW: c_int = 0x2;
+/// Only returns a handle if the object is already loaded, i.e., never loads anything
+pub const RTLD_NOLOAD: c_int = 0x4;
+/// The object's symbols are made available to every other object
+pub const RTLD_GLOBAL: c_int = 0x100;
+/// The object's symbols are only available to itself, its dependencies, and `dlsym` with its handle
+pub const RTLD_LOCAL: c_int = 0;
+/// The object is never unloaded, however many times it's `dlclose`d
+pub const RTLD_NODELETE: c_int = 0x1000;
+
+/// The pseudo handle to search the global scope, like an ordinary reference from the caller would
+pub const RTLD_DEFAULT: isize = 0;
+/// The pseudo handle to search for the next definition after the caller's
+pub const RTLD_NEXT: isize = -1;
+
+/// Which objects `dlsym` searches
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Handle {
+    /// `RTLD_DEFAULT`
+    Default,
+    /// `RTLD_NEXT`
+    Next,
+    /// The object at this index in the link map, and its local scope; the executable's handle, i.e., `dlopen(NULL)`, is the global scope
+    Object(usize),
+}
+
+/// A thread's most recent error, and the one `dlerror` last returned, which has to stay valid until that thread calls it again
+struct Error {
+    thread: u64,
+    pending: Option<CString>,
+    returned: Option<CString>,
+}
+
+/// Every thread's errors, for the threads with one pending or returned; like `resolve::UNIQUE`, a `HashMap` would need our thread locals, and the spin lock is because anything else would need libc's.
+/// A thread's entry goes away once `dlerror` has nothing left to return it, so the table only holds the threads which failed recently, and a new thread which reuses a dead one's thread pointer doesn't inherit its error, unless the dead one never asked for it
+static mut ERRORS: *mut Vec<Error> = 0 as *mut Vec<Error>;
+static ERRORS_LOCK: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Runs `f` on every thread's errors, and the calling thread's thread pointer
+fn with_errors<T, F: FnOnce(&mut Vec<Error>, u64) -> T> (f: F) -> T {
+    while ERRORS_LOCK.compare_and_swap(false, true, Ordering::Acquire) {}
+    let thread = unsafe { tls::thread_pointer() };
+    let result = unsafe {
+        if ERRORS.is_null() {
+            ERRORS = Box::into_raw(Box::new(Vec::new()));
+        }
+        f(&mut *ERRORS, thread)
+    };
+    ERRORS_LOCK.store(false, Ordering::Release);
+    result
+}
+
+/// Records `msg` as the calling thread's error, for its next `dlerror`
+fn set_error(msg: String) {
+    // our messages all start with `<dryad> `, which nobody calling `dlerror` wants to see
+    let msg = if msg.starts_with("<dryad> ") { msg[8..].to_string() } else { msg };
+    let msg = CString::new(msg).unwrap_or(CString::new("dlfcn error").unwrap());
+    with_errors(|errors, thread| {
+        match errors.iter().position(|error| error.thread == thread) {
+            Some (idx) => errors[idx].pending = Some (msg),
+            None => errors.push(Error { thread: thread, pending: Some (msg), returned: None }),
+        }
+    });
+}
+
+/// Returns the calling thread's error, if there's been one since it last asked, and forgets it; the one it returned last time is freed either way, and if there's no new one, so is the thread's entry
+fn take_error() -> *mut c_char {
+    with_errors(|errors, thread| {
+        let idx = match errors.iter().position(|error| error.thread == thread) {
+            Some (idx) => idx,
+            None => return ptr::null_mut(),
+        };
+        if errors[idx].pending.is_none() {
+            errors.swap_remove(idx);
+            return ptr::null_mut()
+        }
+        let error = &mut errors[idx];
+        error.returned = error.pending.take();
+        match error.returned {
+            Some (ref msg) => msg.as_ptr() as *mut c_char,
+            None => ptr::null_mut(),
+        }
+    })
+}
+
+/// Returns the linker, with the link map locked until it's dropped, or records an error if there isn't one yet
+fn get_linker() -> Option<linker::LinkerGuard> {
+    let linker = linker::lock();
+    if linker.is_none() {
+        set_error(format!("dryad is not initialized"));
+    }
+    linker
+}
+
+/// Maps the handle `dlopen` returned, or one of the pseudo handles, to what `dlsym` should search, or `None` if it's not a handle at all
+fn to_handle(linker: &linker::Linker, handle: *mut c_void) -> Option<Handle> {
+    match handle as isize {
+        RTLD_DEFAULT => Some (Handle::Default),
+        RTLD_NEXT => Some (Handle::Next),
+        _ => linker.handle_index(handle as *const LinkNode).map(Handle::Object),
+    }
+}
+
+/// `dlopen`, which `arch/x86/asm.s` calls with the caller's address
+#[no_mangle]
+pub unsafe extern fn dryad_dlopen(filename: *const c_char, flags: c_int, caller: u64) -> *mut c_void {
+    if flags & (RTLD_LAZY | RTLD_NOW) == 0 {
+        set_error(format!("invalid mode for dlopen(): {:#x}", flags));
+        return ptr::null_mut()
+    }
+    let mut linker = match get_linker() {
+        Some (linker) => linker,
+        None => return ptr::null_mut(),
+    };
+    let filename = if filename.is_null() {
+        None
+    } else {
+        match CStr::from_ptr(filename).to_str() {
+            Ok (filename) => Some (filename),
+            Err (_) => {
+                set_error(format!("invalid file name for dlopen()"));
+                return ptr::null_mut()
+            }
+        }
+    };
+    match linker.dlopen(filename, flags, caller) {
+        Ok (Some (idx)) => linker.handle(idx) as *mut c_void,
+        // `RTLD_NOLOAD` and it isn't loaded; not an error
+        Ok (None) => ptr::null_mut(),
+        Err (msg) => {
+            set_error(msg);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// `dlsym`, which `arch/x86/asm.s` calls with the caller's address
+#[no_mangle]
+pub unsafe extern fn dryad_dlsym(handle: *mut c_void, symbol: *const c_char, caller: u64) -> *mut c_void {
+    let linker = match get_linker() {
+        Some (linker) => linker,
+        None => return ptr::null_mut(),
+    };
+    let handle = match to_handle(&linker, handle) {
+        Some (handle) => handle,
+        None => {
+            set_error(format!("invalid handle {:?} for dlsym()", handle));
+            return ptr::null_mut()
+        }
+    };
+    if symbol.is_null() {
+        set_error(format!("invalid symbol name NULL for dlsym()"));
+        return ptr::null_mut()
+    }
+    let name = CStr::from_ptr(symbol).to_string_lossy();
+    match linker.dlsym(handle, &name, caller) {
+        Ok (address) => address as *mut c_void,
+        Err (msg) => {
+            set_error(msg);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Drops a reference to the object `handle` was opened as; once nothing references it, it's finalized and unmapped, along with any of its dependencies nothing else references. Returns 0 on success
+#[no_mangle]
+pub unsafe extern fn dlclose(handle: *mut c_void) -> c_int {
+    let mut linker = match get_linker() {
+        Some (linker) => linker,
+        None => return -1,
+    };
+    let idx = match linker.handle_index(handle as *const LinkNode) {
+        Some (idx) => idx,
+        None => {
+            set_error(format!("invalid handle {:?} for dlclose()", handle));
+            return -1
+        }
+    };
+    match linker.dlclose(idx) {
+        Ok (()) => 0,
+        Err (msg) => {
+            set_error(msg);
+            -1
+        }
+    }
+}
+
+/// Returns a description of the calling thread's most recent `dl*` error, or NULL if there hasn't been one since its last call
+#[no_mangle]
+pub extern fn dlerror() -> *mut c_char {
+    take_error()
+}
+
@@ -2,6 +2,7 @@ use auxv;
 use utils::*;
 
 use std::slice;
+use std::ptr;
 
 const AUX_CNT:usize = 38;
 
@@ -42,6 +43,22 @@ impl<'b> KernelBlock<'b> {
         None
     }
 
+    /// Overwrites the value of the auxiliary vector entry `t` in place; returns `false` if the kernel never gave us one
+    pub unsafe fn setauxval(&self, t:u64, val: u64) -> bool {
+        let mut i = 0;
+        loop {
+            let v = &mut *self.auxv.offset(i);
+            if v.a_type == auxv::AT_NULL {
+                return false
+            }
+            if v.a_type == t {
+                v.a_val = val;
+                return true
+            }
+            i += 1;
+        }
+    }
+
     // TODO: add auxc and make auxv a slice of auxv_t
     pub fn new<'a> (args: *const u64) -> KernelBlock<'a> {
         unsafe {
@@ -96,3 +113,27 @@ impl<'b> KernelBlock<'b> {
         write(&"\n");
     }
 }
+
+/// Removes `argv[0]` from the raw kernel argument block in place, by decrementing `argc` and sliding the rest of `argv`, `envp` and the auxiliary vector down a word.
+/// This is what makes `dryad.so.1 <program> [args...]` look to `<program>` like the kernel executed it directly;
+/// any `KernelBlock` built from `args` beforehand is stale afterwards and must be rebuilt.
+pub unsafe fn shift_args(args: *const u64) {
+    let args = args as *mut u64;
+    let argc = *args;
+    let argv = args.offset(1);
+    // skip over argv and its null terminator
+    let mut end = argv.offset(argc as isize + 1);
+    // then envp and its null terminator
+    while *end != 0 {
+        end = end.offset(1);
+    }
+    end = end.offset(1);
+    // and finally the auxv pairs, up to and including AT_NULL
+    while *end != auxv::AT_NULL {
+        end = end.offset(2);
+    }
+    end = end.offset(2);
+    let count = (end as usize - argv as usize) / 8 - 1;
+    ptr::copy(argv.offset(1), argv, count);
+    *args = argc - 1;
+}
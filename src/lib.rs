@@ -10,29 +10,29 @@
 
 /// Dryad --- the world's first non-functional, yet-to-be-implemented, might be impossible or more likely inefficient --- parallel, dynamic linker.
 /// Many, many thanks to Mutabah, durka42, aatch, tilpner, niconii, bluss, steveklabnik and so many others on the IRC channel for answering my stupid questions.
-/// TODO: add support for invoking ./dryad <binary> <args>
 
+#[macro_use]
+mod utils;
+mod arch;
 mod auxv;
 mod kernel_block;
-mod utils;
 mod binary;
+mod tls;
+mod search_path;
+mod cache;
+mod resolve;
+mod r_debug;
 pub mod linker;
-
-use std::mem;
+pub mod dlfcn;
 
 use kernel_block::KernelBlock;
+use binary::elf::header;
+use binary::elf::program_header;
 use utils::*;
+use utils::debug;
 
 extern crate libc;
 
-// below is gcc attrs for this function...
-//extern "C"
-//void __attribute__((noinline)) __attribute__((visibility("default")))
-// unused; someone figure out how to get gdb working when running as a dyld
-extern {
-    fn rtld_db_dlactivity();
-}
-
 extern {
     /// ELF abi requires `_start`; this must be in assembly because we need
     /// the raw stack pointer as the argument to `_dryad_init`;
@@ -42,41 +42,82 @@ extern {
     fn _start();
 }
 
+/// When the kernel runs dryad as the program itself, `AT_BASE` is 0 and `AT_PHDR` points at _our_ program headers instead, from which we recover our base
+unsafe fn compute_base(block: &KernelBlock) -> u64 {
+    let phdr_addr = block.getauxval(auxv::AT_PHDR).unwrap();
+    let phnum = block.getauxval(auxv::AT_PHNUM).unwrap();
+    let phdrs = program_header::to_phdr_array(phdr_addr as *const program_header::ProgramHeader, phnum as usize);
+    for phdr in phdrs {
+        if phdr.p_type == program_header::PT_PHDR {
+            return phdr_addr - phdr.p_offset
+        }
+    }
+    // no PT_PHDR, so the program headers immediately follow the elf header
+    phdr_addr - header::EHDR_SIZE as u64
+}
+
 #[no_mangle]
 pub extern fn _dryad_init (raw_args: *const u64) -> u64 {
 
     // the linker is currently tied to the lifetime of the kernel block... but really it's static
     let block = KernelBlock::new(raw_args);
-    let linker_base = block.getauxval(auxv::AT_BASE).unwrap();
-    let entry  = block.getauxval(auxv::AT_ENTRY).unwrap();
+    let mut entry  = block.getauxval(auxv::AT_ENTRY).unwrap();
 
-    let start_addr = _start as *const u64 as u64;    
-    // TODO: add support for invoking ./dryad <binary> <args>
-    if start_addr == entry {
+    let start_addr = _start as *const u64 as u64;
+    // we were invoked as `dryad.so.1 <program> [args...]`
+    let invoked_directly = start_addr == entry;
+
+    if invoked_directly && block.argc < 2 {
         // because it's _tradition_
         // (https://fossies.org/dox/glibc-2.22/rtld_8c_source.html)
         // line 786:
         // > Ho ho.  We are not the program interpreter!  We are the program itself!
-        unsafe { write(&"-=|dryad====-\nHo ho.  We are not the program interpreter!  We are the program itself!\n"); } // TODO: add box drawing random character gen here cause it'll be _cute_
+        unsafe { write(&"-=|dryad====-\nHo ho.  We are not the program interpreter!  We are the program itself!\nusage: dryad.so.1 <program> [args...]\n"); } // TODO: add box drawing random character gen here cause it'll be _cute_
         _exit(0);
         return 0xd47ad // to make compiler happy
     }
 
+    let (linker_base, block) = if invoked_directly {
+        // nothing here allocates, so it's safe to do before we've relocated ourselves
+        unsafe {
+            let base = compute_base(&block);
+            kernel_block::shift_args(raw_args);
+            (base, KernelBlock::new(raw_args))
+        }
+    } else {
+        (block.getauxval(auxv::AT_BASE).unwrap(), block)
+    };
+
     match linker::Linker::new(linker_base, &block) {
-        Ok (mut dryad) => {
-            println!("Dryad:\n  {:#?}", &dryad);
-
-            if let Err(msg) = dryad.link(&block) {
-                println!("{}", msg);
-                _exit(1);
-                0xd47ad
-            } else {
-                // verify minimum forget parameters, this comment could be obsolete: if we don't forget the entire dryad linker then internal heap-allocated strings get corrupted, like SharedObject.name
-                // "Blessed are the forgetful, for they get the better even of their blunders."
-                // "Without forgetting it is quite impossible to live at all."
-                mem::forget(dryad);
-                entry
-            }
+        Ok (dryad) => {
+            let initializers = {
+                // `dlopen` and friends need the linker once the program is running, so it's never dropped
+                let mut dryad = linker::install(dryad);
+                debug!(debug::FILES, "dryad:\n  {:#?}", &*dryad);
+
+                if invoked_directly {
+                    match dryad.load_program(&block) {
+                        Ok (program_entry) => entry = program_entry,
+                        Err (msg) => {
+                            error!("{}", msg);
+                            _exit(1);
+                            return 0xd47ad
+                        }
+                    }
+                }
+
+                match dryad.link(&block) {
+                    Ok (initializers) => initializers,
+                    Err (msg) => {
+                        error!("{}", msg);
+                        _exit(1);
+                        return 0xd47ad
+                    }
+                }
+            };
+            // the constructors can lock the link map themselves, so the guard has to be gone by now; all the more so since `link` switched us to the program's thread pointer, which is what the lock is keyed on
+            linker::run_init(&initializers);
+            entry
         },
         Err (msg) => {
             // relocating self failed somehow; we try to write the error message and exit
@@ -2,18 +2,23 @@
 // Questions from README:
 // 1. Is the `rela` _always_ in a `PT_LOAD` segment?
 // 2. Is the `strtab` _always_ after the `symtab` in terms of binary offset, and hence we can compute the size of the symtab by subtracting the two?
+//    No; see `hash::num_syms`
 // TODO: LOAD THE VDSO: linux-vdso.so.1
-// TODO: implement the gnu symbol lookup with bloom filter
 // TODO: use link_map
-// TODO: compute flattened dependency list and relocate in order (not using hashmap values)
 // start linking some symbols!
-use std::collections::HashMap;
 use std::boxed::Box;
 use std::slice;
 use std::fmt;
 use std::mem;
+use std::ptr;
+use std::ops::{Deref, DerefMut};
+use std::cmp;
 use std::fs::File;
 use std::path::Path;
+use std::io;
+use std::io::{Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_BOOL_INIT, ATOMIC_USIZE_INIT};
 
 //use scoped_thread::Pool;
 //use std::thread;
@@ -23,24 +28,51 @@ use binary::elf::header;
 use binary::elf::program_header;
 use binary::elf::dyn;
 use binary::elf::rela;
+use binary::elf::rel;
 use binary::elf::loader;
+use binary::elf::sym;
 use binary::elf::image::SharedObject;
+use binary::elf::version;
 
 use utils;
+use utils::mmap;
+use utils::debug;
 use kernel_block;
 use auxv;
+use tls;
+use search_path;
+use cache;
+use resolve;
+use r_debug;
+use dlfcn;
+use arch::x86::cpu_features;
 
 //thread_local!(static FOO: u32 = 0xdeadbeef);
 
 /// The internal config the dynamic linker generates from the environment variables it receives.
 struct Config<'a> {
     bind_now: bool,
-    debug: bool,
+    /// The `LD_DEBUG` categories, see `utils::debug`
+    debug: u32,
+    /// Whether `LD_DEBUG=help` was given
+    debug_help: bool,
+    /// Where to write the `LD_DEBUG` output instead of stderr
+    debug_output: Option<&'a str>,
     secure: bool,
     verbose: bool,
     trace_loaded_objects: bool,
-    library_path: Vec<&'a str>,
-    preload: &'a[&'a str]
+    /// Report undefined symbols when tracing, like `ldd -d`, or `ldd -r` along with `bind_now`
+    warn: bool,
+    /// The raw `LD_LIBRARY_PATH`; it's expanded relative to the executable, so can only be split once we know where that is
+    library_path: Option<&'a str>,
+    /// What `$PLATFORM` expands to
+    platform: &'a str,
+    /// `AT_HWCAP`, which selects between the variants of a library in the `ld.so.cache`
+    hwcap: u64,
+    /// The `ld.so.cache` to use; `LD_SO_CACHE` can point it at one `ldconfig -C` built from a local `ld.so.conf`, e.g., for tests
+    cache_path: &'a str,
+    /// The objects in `LD_PRELOAD`, in order
+    preload: Vec<&'a str>,
 }
 
 impl<'a> Config<'a> {
@@ -49,49 +81,68 @@ impl<'a> Config<'a> {
         // http://flint.cs.yale.edu/cs422/doc/ELF_Format.pdf
         let bind_now = if let Some (var) = block.getenv("LD_BIND_NOW") {
             var != "" } else { false };
-        let debug = if let Some (var) = block.getenv("LD_DEBUG") {
-            var != "" } else { false };
-        // TODO: FIX THIS IS NOT VALID and massively unsafe
-        let secure = block.getauxval(auxv::AT_SECURE).unwrap() != 0;
+        // the kernel always passes AT_SECURE, but if it somehow didn't, assume the worst
+        let secure = block.getauxval(auxv::AT_SECURE).map(|secure| secure != 0).unwrap_or(true);
+        // like ld-so, a setuid program only honors LD_DEBUG if the administrator allows it, and never writes the output to a file the user chose
+        let (debug, debug_help) = match block.getenv("LD_DEBUG") {
+            Some (var) if !secure || Path::new(SUID_DEBUG_PATH).exists() => debug::parse(var),
+            _ => (0, false)
+        };
+        let debug_output = if secure { None } else { block.getenv("LD_DEBUG_OUTPUT") };
         // TODO: add different levels of verbosity
         let verbose = if let Some (var) = block.getenv("LD_VERBOSE") {
             var != "" } else { false };
         let trace_loaded_objects = if let Some (var) = block.getenv("LD_TRACE_LOADED_OBJECTS") {
             var != "" } else { false };
-        let library_path =
-            if let Some (paths) = block.getenv("LD_LIBRARY_PATH") {
-                // we don't need to allocate since technically the strings are preallocated in the environment variable, but being lazy for now
-                let mut dirs: Vec<&str> = vec![];
-                if !secure {
-                    dirs.extend(paths.split(":").collect::<Vec<&str>>());
-                }
-                dirs.push("/usr/lib");
-                dirs
-            } else { 
-                vec!["/usr/lib"]
-            };
+        let warn = if let Some (var) = block.getenv("LD_WARN") {
+            var != "" } else { false };
+        // a setuid program mustn't be tricked into loading the user's libraries
+        let library_path = if secure { None } else { block.getenv("LD_LIBRARY_PATH") };
+        let platform = match block.getauxval(auxv::AT_PLATFORM) {
+            Some (platform) if platform != 0 => utils::as_str(platform as *const u8),
+            _ => search_path::DEFAULT_PLATFORM,
+        };
+        // a setuid program only preloads by name, and then only setuid libraries from the default dirs; see `Linker::preload`
+        let preload = match block.getenv("LD_PRELOAD") {
+            Some (preload) => preload.split(|c| c == ' ' || c == ':')
+                .filter(|name| *name != "" && !(secure && name.contains('/')))
+                .collect(),
+            None => Vec::new(),
+        };
+        let hwcap = block.getauxval(auxv::AT_HWCAP).unwrap_or(0);
+        let cache_path = if secure { None } else { block.getenv("LD_SO_CACHE") };
         Config {
             bind_now: bind_now,
             debug: debug,
+            debug_help: debug_help,
+            debug_output: debug_output,
             secure: secure,
             verbose: verbose,
             trace_loaded_objects: trace_loaded_objects,
-            //TODO: finish path logics
+            warn: warn,
             library_path: library_path,
-            preload: &[],
+            platform: platform,
+            hwcap: hwcap,
+            cache_path: cache_path.unwrap_or(cache::CACHE_PATH),
+            preload: preload,
         }
     }
 }
 
 impl<'a> fmt::Debug for Config<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "bind_now: {} debug: {} secure: {} verbose: {} trace_loaded_objects: {} library_path: {:#?} preload: {:#?}",
+        write!(f, "bind_now: {} debug: {:#x} debug_output: {:?} secure: {} verbose: {} trace_loaded_objects: {} warn: {} library_path: {:?} platform: {} hwcap: {:#x} cache_path: {} preload: {:#?}",
                self.bind_now,
                self.debug,
+               self.debug_output,
                self.secure,
                self.verbose,
                self.trace_loaded_objects,
+               self.warn,
                self.library_path,
+               self.platform,
+               self.hwcap,
+               self.cache_path,
                self.preload
                )
     }
@@ -108,12 +159,58 @@ fn compute_load_bias(base:u64, phdrs:&[program_header::ProgramHeader]) -> u64 {
     0
 }
 
-/*
+/// The system wide list of objects to preload, which, unlike `LD_PRELOAD`, only root can write to
+const PRELOAD_PATH: &'static str = "/etc/ld.so.preload";
+
+/// If this exists, `LD_DEBUG` is honored even by setuid programs
+const SUID_DEBUG_PATH: &'static str = "/etc/suid-debug";
+
+/// The set-user-ID bit of a file's mode
+const S_ISUID: u32 = 0o4000;
+
+/// What we call ourselves in the debugger's link map when the executable has no `PT_INTERP`
+const SONAME: &'static str = "dryad.so.1";
+
+/// The sonames of the dynamic linkers we stand in for; a `DT_NEEDED` on any of them, e.g., libc's on glibc's ld.so, is satisfied by dryad itself, see `is_rtld_name`
+const RTLD_SONAMES: [&'static str; 2] = [SONAME, "ld-linux-x86-64.so.2"];
+
+/// The finalizers `_dryad_fini` calls, in the order it calls them; the link map is leaked when we transfer control, so these stay valid for the life of the process
+static mut FINALIZERS: *const Vec<u64> = 0 as *const Vec<u64>;
+static FINALIZED: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// `_start` hands this to the program in `%rdx`, which libc registers with `atexit`, so it runs the fini arrays and `DT_FINI` of every object still loaded, dependents before their dependencies.
+/// Runs on the program's TLS, so it mustn't print or touch any of dryad's thread locals
 #[no_mangle]
 pub extern fn _dryad_fini() {
-    return
+    if FINALIZED.swap(true, Ordering::SeqCst) {
+        return
+    }
+    // whatever the program `dlopen`ed and never closed depends on what it loaded at startup, so it goes first
+    if let Some(linker) = lock() {
+        let finalizers = linker.dlopen_finalizers();
+        for fini in finalizers {
+            unsafe { mem::transmute::<u64, extern fn()>(fini)(); }
+        }
+    }
+    unsafe {
+        if FINALIZERS.is_null() {
+            return
+        }
+        for fini in &*FINALIZERS {
+            let fini = mem::transmute::<u64, extern fn()>(*fini);
+            fini();
+        }
+    }
+}
+
+/// The calling convention for `DT_INIT`, `DT_INIT_ARRAY` and `DT_PREINIT_ARRAY` functions; glibc passes them the program arguments and environment, and plenty of libraries rely on it
+type InitFn = extern fn(isize, *const *const u8, *const *const u8);
+
+/// Some linkers terminate or pad init and fini arrays with 0 or -1
+#[inline(always)]
+fn is_valid_function(addr: u64) -> bool {
+    addr != 0 && addr != !0
 }
-*/
 
 unsafe fn get_linker_relocations(bias: u64, dynamic: &[dyn::Dyn]) -> &[rela::Rela] {
     let mut rela = 0;
@@ -143,7 +240,8 @@ fn relocate_linker(bias: u64, relas: &[rela::Rela]) {
         if rela::r_type(rela.r_info) == rela::R_X86_64_DTPMOD64 {
             let reloc = (rela.r_offset + bias) as *mut u64;
             unsafe {
-                *reloc = 0; // lol seriously ?
+                // as far as musl's static `__tls_get_addr` is concerned, dryad is its own, and only, TLS module
+                *reloc = 1;
             }
         }
         if rela::r_type(rela.r_info) == rela::R_X86_64_RELATIVE {
@@ -169,8 +267,6 @@ extern {
 }
 
 /// The dynamic linker
-/// TODO: remove working set from mem::forget as the got[1] entry, and instead add the flattened link_map as the rendevous structure that is one-time allocated and then forgotten (then reconstituted back in dryad_resolve_symbol)
-/// TODO: add lib vector or lib working_set and lib finished_set
 /// TODO: Change permissions on most of these fields
 pub struct Linker<'process> {
     // TODO: maybe remove base
@@ -181,9 +277,20 @@ pub struct Linker<'process> {
     pub phdrs: &'process [program_header::ProgramHeader],
     pub dynamic: &'process [dyn::Dyn],
     config: Config<'process>,
-    working_set: Box<HashMap<String, SharedObject<'process>>>,
-    link_map_order: Vec<String>,
-    link_map: Vec<SharedObject<'process>>,
+    /// Every loaded object, breadth first from the executable; this is also the global scope, searched in order.
+    /// It's boxed so the `LinkNode`s can point at it
+    link_map: Box<Vec<SharedObject<'process>>>,
+    /// The `LinkNode` of each object in the link map, in the same order; these double as `dlopen` handles
+    nodes: Vec<Box<LinkNode>>,
+    /// The link map index of dryad itself, once it's been added, see `rtld`
+    rtld: Option<usize>,
+    /// How many objects were loaded at startup; everything after them was `dlopen`ed
+    startup: usize,
+    /// `argc`, `argv` and `envp`, which every constructor gets, including the ones `dlopen` runs
+    args: (isize, *const *const u8, *const *const u8),
+    /// The `ld.so.cache`, if there is one and it's valid
+    cache: Option<cache::Cache>,
+    tls: Box<tls::Tls>,
 //    link_map: Vec<LinkData<'process>>,
     // TODO: add a set of SharedObject names which a dryad thread inserts into after stealing work to load a SharedObject;
     // this way when other threads check to see if they should load a dep, they can skip from adding it to the set because it's being worked on
@@ -210,29 +317,198 @@ extern {
     fn _dryad_resolve_symbol();
 }
 
+/// The node `prepare_got` puts in an object's GOT[1], which its PLT pushes for `_dryad_resolve_symbol`, identifying the object whose symbol is to be bound.
+/// It's boxed, so it stays put however many objects are loaded later
+pub struct LinkNode {
+    /// The object's index in the link map
+    pub idx: usize,
+}
+
+/// The thread pointer of the thread holding the link map lock, or 0
+static LINK_MAP_OWNER: AtomicUsize = ATOMIC_USIZE_INIT;
+/// How many times the owner has taken the lock; only the owner touches this
+static mut LINK_MAP_DEPTH: usize = 0;
+
+/// Serializes everything that reads or changes the link map once the program is running, i.e., lazy binding, `dlopen`, `dlsym` and `dlclose`, since any number of threads can be doing any of them at once.
+/// It's a spin lock because everything else would need our thread locals, or worse, libc's; and it's reentrant, since the constructors, finalizers and IFUNC resolvers we run while holding it can call through unbound PLT slots, or `dlopen` themselves.
+/// Only `LinkerGuard` takes it for the linker; the table of unique symbols, which is global, takes it directly
+pub fn lock_link_map() {
+    let me = unsafe { tls::thread_pointer() } as usize;
+    if LINK_MAP_OWNER.load(Ordering::Acquire) != me {
+        while LINK_MAP_OWNER.compare_and_swap(0, me, Ordering::Acquire) != 0 {}
+    }
+    unsafe { LINK_MAP_DEPTH += 1; }
+}
+
+pub fn unlock_link_map() {
+    unsafe {
+        LINK_MAP_DEPTH -= 1;
+        if LINK_MAP_DEPTH == 0 {
+            LINK_MAP_OWNER.store(0, Ordering::Release);
+        }
+    }
+}
+
+/// The linker, once `_dryad_init` has created it; `dlopen` and friends need it for the life of the process, so it's never dropped.
+/// It's only ever dereferenced by a `LinkerGuard`, i.e., with the link map lock held, except for the program's arguments, which `run_init` reads, and which never change once `link` has set them
+static mut LINKER: *mut Linker<'static> = 0 as *mut Linker<'static>;
+
+/// The `install`ed linker, with the link map locked for as long as this lives; it's the only way to get at the linker, so however many threads are binding lazily, or calling `dlopen` and friends, only one of them ever borrows it at a time.
+/// The lock is reentrant, and the constructors and finalizers `dlopen` and `dlclose` run can take it again, so they're only ever called once the guard's borrow has ended, see `LinkerGuard::dlopen`;
+/// an IFUNC resolver, on the other hand, is called in the middle of relocating, so like with glibc it mustn't call through a PLT slot which isn't bound yet
+pub struct LinkerGuard {
+    linker: *mut Linker<'static>,
+}
+
+impl Deref for LinkerGuard {
+    type Target = Linker<'static>;
+    fn deref(&self) -> &Linker<'static> {
+        unsafe { &*self.linker }
+    }
+}
+
+impl DerefMut for LinkerGuard {
+    fn deref_mut(&mut self) -> &mut Linker<'static> {
+        unsafe { &mut *self.linker }
+    }
+}
+
+impl Drop for LinkerGuard {
+    fn drop(&mut self) {
+        unlock_link_map();
+    }
+}
+
+/// Moves `linker` to the heap, where it stays for the rest of the process, and returns it, locked; see `lock`
+pub fn install (linker: Linker) -> LinkerGuard {
+    unsafe {
+        // everything the linker borrows, i.e., the kernel's argument block and the objects' mappings, lives as long as the process does
+        LINKER = Box::into_raw(Box::new(mem::transmute::<Linker, Linker<'static>>(linker)));
+    }
+    lock_link_map();
+    LinkerGuard { linker: unsafe { LINKER } }
+}
+
+/// Locks the link map and returns the `install`ed linker, or `None` if `_dryad_init` hasn't gotten that far
+pub fn lock () -> Option<LinkerGuard> {
+    let linker = unsafe { LINKER };
+    if linker.is_null() {
+        return None
+    }
+    lock_link_map();
+    Some (LinkerGuard { linker: linker })
+}
+
+/// Calls each of `initializers` with the program's arguments and environment.
+/// Constructors can call `dlopen`, or through unbound PLT slots, which lock the link map themselves, so nothing may be borrowing the linker while they run; runs on the program's TLS, so it mustn't print
+pub fn run_init (initializers: &[u64]) {
+    let (argc, argv, envp) = unsafe { (*LINKER).args };
+    for init in initializers {
+        unsafe { mem::transmute::<u64, InitFn>(*init)(argc, argv, envp); }
+    }
+}
+
+/// Called by `_dryad_resolve_symbol` the first time a PLT slot is called through: binds the symbol of the `rela_idx`th PLT relocation of the object `node` identifies, patches the slot so later calls go straight to the definition, and returns the definition for the trampoline to jump to.
+/// There's nowhere to return an error to, so if the symbol is undefined the program dies here, like with glibc, instead of jumping to garbage; runs on the program's TLS, so it mustn't use ours, which is also why its logging and error are formatted on the stack
 #[no_mangle]
-pub extern fn dryad_resolve_symbol (link_map_ptr: *const usize, rela_idx: usize) -> usize {
+pub extern fn dryad_resolve_symbol (node: *const LinkNode, rela_idx: usize) -> usize {
     unsafe {
-        println!("<dryad_resolve_symbol> link_map_ptr: {:#?} rela_idx: {}", link_map_ptr, rela_idx);
-        let rdvz = Box::from_raw(link_map_ptr as *mut (usize, *mut SharedObject, usize));
-        let idx = rdvz.0;
-        let link_map: &[SharedObject] = slice::from_raw_parts(rdvz.1, rdvz.2);
-        let requesting_so = &link_map[idx];
-        let rela = &requesting_so.pltrelatab[rela_idx];
-        let requested_symbol = &requesting_so.symtab[rela::r_sym(rela.r_info) as usize];
-        let name = &requesting_so.strtab[requested_symbol.st_name as usize];
-        println!("<dryad_resolve_symbol> reconstructed link_map of size {} with requesting binary {:#?} for symbol with rela idx {} for symbol {}", link_map.len(), requesting_so.name, rela_idx, name);
-        for (i, so) in link_map.iter().enumerate() {
-            println!("i: {}", i);
-            if let Some (symbol) = so.find(name) {
-                println!("<dryad_resolve_symbol> binding \"{}\" in {} to {} at address 0x{:x}", name, so.name, requesting_so.name, symbol);
-                mem::forget(rdvz); // otherwise it gets dropped and is corrupted
-                return symbol as usize
-            }
-        }
-        println!("<dryad_resolve_symbol> Uh-oh, symbol {} not found, about to return a 0xdeadbeef sandwhich for you to munch on, goodbye!", name);
-        0xdeadbeef // lel
+        // a `dlopen` on another thread could be growing the link map; every object with a `LinkNode` was loaded by the installed linker
+        let mut linker = match lock() {
+            Some (linker) => linker,
+            None => {
+                utils::_exit(127);
+                return 0
+            }
+        };
+        let idx = (*node).idx;
+        let (definer, symbol) = {
+            let link_map: &[SharedObject] = &linker.link_map;
+            let requesting_so = &link_map[idx];
+            // the index is into whichever of the PLT relocation tables the object has
+            let (r_offset, r_info) = if requesting_so.pltreltab.is_empty() {
+                let rela = &requesting_so.pltrelatab[rela_idx];
+                (rela.r_offset, rela.r_info)
+            } else {
+                let rel = &requesting_so.pltreltab[rela_idx];
+                (rel.r_offset, rel.r_info)
+            };
+            let sym = rela::r_sym(r_info) as usize;
+            let requested_symbol = &requesting_so.symtab[sym];
+            let name = &requesting_so.strtab[requested_symbol.st_name as usize];
+            debug!(debug::SYMBOLS, "symbol={};  lookup in file={} [{}]", name, requesting_so.name, rela_idx);
+
+            let symbol = resolve::resolve_address(link_map, idx, sym, resolve::Class::Plt);
+            match symbol {
+                Some ((definer, symbol)) => {
+                    // another thread may have gotten here first, but it wrote the same address, and an aligned store is atomic
+                    *((r_offset + requesting_so.load_bias) as *mut u64) = symbol;
+                    (definer, symbol)
+                },
+                None => {
+                    // still holding the lock, since a `dlclose` could otherwise unmap the names out from under us; `_exit` takes every other thread down with us, so nobody's left waiting on it
+                    error!("{}", undefined_symbol(link_map, requesting_so, sym));
+                    utils::_exit(127);
+                    return 0
+                }
+            }
+        };
+        add_reldep(&mut linker.link_map, idx, definer);
+        symbol as usize
+    }
+}
+
+/// Records that the object at `idx` bound a symbol to the object at `definer`, like glibc's `l_reldeps`: unless `definer` is in its local scope, and so already held by its dependencies, or can never be unloaded anyway, `idx` takes a reference to it, which `Linker::release` drops along with `idx`'s others
+fn add_reldep (link_map: &mut [SharedObject], idx: usize, definer: usize) {
+    if definer == idx || link_map[definer].flags_1 & dyn::DF_1_NODELETE != 0 || link_map[idx].scope.contains(&definer) || link_map[idx].reldeps.contains(&definer) {
+        return
+    }
+    debug!(debug::BINDINGS, "object={} [{}] now depends on {} [{}]", link_map[idx].name, idx, link_map[definer].name, definer);
+    link_map[idx].reldeps.push(definer);
+    link_map[definer].refcount += 1;
+}
+
+/// The error for a strong reference to a symbol which nothing defines, in the words of glibc's ld.so; it's displayed straight into the error, rather than formatted into a `String`, so the lazy binding trampoline can report it without allocating
+struct UndefinedSymbol<'a> {
+    executable: &'a str,
+    object: &'a str,
+    name: &'a str,
+    version: Option<&'a str>,
+}
+
+impl<'a> fmt::Display for UndefinedSymbol<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "<dryad> {}: symbol lookup error: {}: undefined symbol: {}", self.executable, self.object, self.name));
+        match self.version {
+            Some (version) => write!(f, ", version {}", version),
+            None => Ok (()),
+        }
+    }
+}
+
+/// The error for a strong reference to the symbol at index `sym` in `object` which nothing defines, e.g., libc's to glibc's `GLIBC_PRIVATE` ld.so internals, which we don't provide
+fn undefined_symbol<'a> (link_map: &'a [SharedObject], object: &'a SharedObject<'a>, sym: usize) -> UndefinedSymbol<'a> {
+    UndefinedSymbol {
+        executable: &link_map[0].path,
+        object: &object.path,
+        name: &object.strtab[object.symtab[sym].st_name as usize],
+        version: object.versions.version(sym).map(|version| version.name.as_str()),
+    }
+}
+
+/// The error for a TLS relocation `rela` in `object`, against the symbol `name`, when the symbol isn't defined, or its definer has no TLS block; leaving it unrelocated would just crash the first access to it
+fn no_tls_module (object: &SharedObject, rela: &rela::Rela, name: &str) -> String {
+    format!("<dryad> {}: no TLS module for `{}' at offset {:#x} needed by {}", object.name, name, rela.r_offset, rela::type_to_str(rela::r_type(rela.r_info)))
+}
+
+/// Writes the 32-bit field of the relocation `rela` in `object`, which has to hold `value`, sign or zero extended; if it doesn't, the object was built for the small code model and loaded too far from what it references
+unsafe fn relocate32 (object: &SharedObject, rela: &rela::Rela, value: i64, signed: bool) -> Result<(), String> {
+    let fits = if signed { value == value as i32 as i64 } else { value == value as u32 as i64 };
+    if !fits {
+        return Err(format!("<dryad> {}: relocation {} at offset {:#x} overflows with {:#x}", object.name, rela::type_to_str(rela::r_type(rela.r_info)), rela.r_offset, value))
     }
+    *((rela.r_offset + object.load_bias) as *mut u32) = value as u32;
+    Ok (())
 }
 
 /// TODO:
@@ -265,8 +541,18 @@ impl<'process> Linker<'process> {
                  */
 
                 // we relocated ourselves so it should be safe to heap allocate
-                let working_set = Box::new(HashMap::new());
-
+                let config = Config::new(&block);
+                if config.debug_help {
+                    debug::help();
+                }
+                debug::init(config.debug, config.debug_output);
+                let cache = match cache::Cache::open(config.cache_path) {
+                    Ok (cache) => Some (cache),
+                    Err (msg) => {
+                        debug!(debug::LIBS, "{}", msg);
+                        None
+                    }
+                };
                 Ok (Linker {
                     base: base,
                     load_bias: load_bias,
@@ -274,10 +560,14 @@ impl<'process> Linker<'process> {
                     ehdr: &ehdr,
                     phdrs: &phdrs,
                     dynamic: &dynamic,
-                    config: Config::new(&block),
-                    working_set: working_set,
-                    link_map_order: Vec::new(),
-                    link_map: Vec::new(),
+                    config: config,
+                    link_map: Box::new(Vec::new()),
+                    nodes: Vec::new(),
+                    rtld: None,
+                    startup: 0,
+                    args: (0, ptr::null(), ptr::null()),
+                    cache: cache,
+                    tls: Box::new(tls::Tls::new()),
                 })
 
             } else {
@@ -287,67 +577,365 @@ impl<'process> Linker<'process> {
         }
     }
 
-    /// TODO: rename to something like `load_all` to signify on return everything has loaded?
-    /// So: load many -> join -> relocate many -> join -> relocate executable and transfer control
-    /// 1. Open fd to shared object ✓ - TODO: parse and use /etc/ldconfig.cache
+    /// When dryad is invoked directly as `dryad.so.1 <program> [args...]`, the kernel only mapped _us_;
+    /// so we map `<program>` ourselves, and rewrite the auxiliary vector so that it, and hence the rest of the linking process, describes `<program>` instead of dryad.
+    /// Expects dryad's own `argv[0]` to have already been shifted out of the `block`; returns the program's entry point
+    pub fn load_program(&self, block: &kernel_block::KernelBlock) -> Result<u64, String> {
+        let name = utils::as_str(block.argv[0]);
+        match File::open(Path::new(name)) {
+            Ok (mut fd) => {
+                let (phdr_addr, phnum, entry) = try!(loader::load_program(name, &mut fd));
+                unsafe {
+                    block.setauxval(auxv::AT_PHDR, phdr_addr);
+                    block.setauxval(auxv::AT_PHNUM, phnum as u64);
+                    block.setauxval(auxv::AT_ENTRY, entry);
+                    block.setauxval(auxv::AT_EXECFN, block.argv[0] as u64);
+                    block.setauxval(auxv::AT_BASE, self.base);
+                }
+                Ok (entry)
+            },
+            Err (err) => Err(format!("<dryad> could not open {}: {}", name, err))
+        }
+    }
+
+    /// Returns the link map index of the loaded object satisfying the `DT_NEEDED` entry `soname`, i.e., one that was either loaded under that name, or whose `DT_SONAME` is that name
+    fn find_loaded(&self, soname: &str) -> Option<usize> {
+        self.link_map.iter().position(|so| !so.unloaded && (so.name == soname || so.soname == soname))
+    }
+
+    /// Returns the paths to try opening, in order, for the `DT_NEEDED` entry `soname` of the object at `requester`; see `search_path` for the order
+    fn search(&self, soname: &str, requester: usize) -> Vec<String> {
+        let platform = self.config.platform;
+        let secure = self.config.secure;
+        let object = &self.link_map[requester];
+        let origin = search_path::origin(&object.path);
+
+        if soname.contains('/') {
+            return search_path::expand(soname, origin, platform, secure).into_iter().collect()
+        }
+
+        let mut dirs = Vec::new();
+        // DT_RUNPATH turns off DT_RPATH; otherwise it's the DT_RPATH of the requester, then of whatever loaded it, and so on up to the executable, skipping any of them which has a DT_RUNPATH of its own
+        if object.runpath.is_none() {
+            let mut loader = Some (requester);
+            while let Some(idx) = loader {
+                let so = &self.link_map[idx];
+                if so.runpath.is_none() {
+                    if let Some(ref rpath) = so.rpath {
+                        dirs.extend(search_path::split(rpath, search_path::origin(&so.path), platform, secure));
+                    }
+                }
+                loader = so.loader;
+            }
+        }
+        if let Some(paths) = self.config.library_path {
+            dirs.extend(search_path::split(paths, search_path::origin(&self.link_map[0].path), platform, secure));
+        }
+        if let Some(ref runpath) = object.runpath {
+            dirs.extend(search_path::split(runpath, origin, platform, secure));
+        }
+
+        let mut paths: Vec<String> = dirs.iter().map(|dir| format!("{}/{}", dir, soname)).collect();
+        if let Some(ref cache) = self.cache {
+            if let Some(path) = cache.find(soname, self.config.hwcap) {
+                paths.push(path.to_string());
+            }
+        }
+        paths.extend(search_path::DEFAULT_DIRS.iter().map(|dir| format!("{}/{}", dir, soname)));
+        paths
+    }
+
+    /// Loads the object satisfying the `DT_NEEDED` entry `soname` of the object at `requester`, unless it's already loaded, and returns its index in the link map, or `None` if it can't be found.
+    /// New objects are appended to the link map, so loading every object's dependencies in order, one object after the other, builds the global scope breadth first.
+    /// 1. Open fd to shared object ✓
     /// 2. get program headers ✓
     /// 3. mmap PT_LOAD phdrs ✓
     /// 4. compute load bias and base ✓
     /// 5. get _DYNAMIC real address from the mmap'd segments ✓
-    /// 6a. create SharedObject from above ✓
-    /// 6b. relocate the SharedObject, including GLOB_DAT ✓ TODO: TLS shite
-    /// 6c. resolve function and PLT; for now, just act like LD_PRELOAD is set
-    /// 7. add `soname` => `SharedObject` entry in `linker.loaded` TODO: use better structure, resolve dependency chain
-    fn load(&mut self, soname: &str) -> Result<(), String> {
-        // TODO: properly open the file using soname -> path with something like `resolve_soname`
-//        match OpenOptions::new().read(true).write(true).open(Path::new("/tmp/lib/").join(soname)) {
-
-        let paths = self.config.library_path.to_owned(); // TODO: so we compile, fix unnecessary alloc
-
-        // soname ∉ linker.loaded
-        if !self.working_set.contains_key(soname) {
-            let mut found = false;
-            for path in paths {
-                match File::open(Path::new(&path).join(soname)) {
-                    Ok (mut fd) => {
-                        found = true;
-                        println!("Opened: {:?}", fd);
-                        let shared_object = try!(loader::load(soname, &mut fd));
-                        let libs = &shared_object.libs.to_owned(); // TODO: fix this unnecessary allocation, but we _must_ insert before iterating
-                        self.working_set.insert(soname.to_string(), shared_object);
-
-                        // breadth first addition
-                        self.link_map_order.extend(libs.iter().map(|s| s.to_string()));
-                        
-                        for lib in libs {
-                            try!(self.load(lib));
+    /// 6. create SharedObject from above ✓
+    fn load(&mut self, soname: &str, requester: usize) -> Result<Option<usize>, String> {
+        if let Some(idx) = self.find_loaded(soname) {
+            return Ok (Some (idx))
+        }
+        // we're already loaded, thank you very much
+        if self.is_rtld_name(soname) {
+            return self.rtld().map(Some)
+        }
+
+        let paths = self.search(soname, requester);
+        let first = self.link_map.len();
+        let idx = try!(self.open(soname, paths, false));
+        if let Some(idx) = idx {
+            if idx >= first {
+                self.link_map[idx].loader = Some (requester);
+            }
+        }
+        Ok (idx)
+    }
+
+    /// Opens the first of `paths` which exists and loads it as `soname`, unless it's a file that's already loaded; returns its index in the link map, or `None` if none of them exist.
+    /// If `setuid_only`, files without the set-user-ID bit are skipped
+    fn open(&mut self, soname: &str, paths: Vec<String>, setuid_only: bool) -> Result<Option<usize>, String> {
+        for path in paths {
+            match File::open(Path::new(&path)) {
+                Ok (mut fd) => {
+                    debug!(debug::LIBS, "  trying file={}", path);
+                    let (dev, ino, mode) = match fd.metadata() {
+                        Ok (metadata) => (metadata.dev(), metadata.ino(), metadata.mode()),
+                        Err (_) => (0, 0, 0),
+                    };
+                    if setuid_only && mode & S_ISUID == 0 {
+                        continue
+                    }
+                    // the same file under a different name, e.g., through a symlink
+                    if let Some(idx) = self.link_map.iter().position(|so| !so.unloaded && so.ino != 0 && so.dev == dev && so.ino == ino) {
+                        debug!(debug::FILES, "file={} is already loaded as {}", soname, self.link_map[idx].name);
+                        return Ok (Some (idx))
+                    }
+                    let mut shared_object = try!(loader::load(soname, &mut fd));
+                    shared_object.path = path;
+                    shared_object.dev = dev;
+                    shared_object.ino = ino;
+                    self.link_map.push(shared_object);
+                    return Ok (Some (self.link_map.len() - 1))
+                },
+                _ => (),
+            }
+        }
+        Ok (None)
+    }
+
+    /// Returns the link map index of dryad itself, adding it to the link map the first time; `link` does that right after the executable and any preloaded objects, so that what dryad exports, i.e., `__tls_get_addr` and `dlopen` and friends, comes before anything libc, or a stray copy of another dynamic linker, might define.
+    /// It's already relocated, and has no business being initialized, traced, or relocated again, see `is_rtld`
+    fn rtld(&mut self) -> Result<usize, String> {
+        if let Some(idx) = self.rtld {
+            return Ok (idx)
+        }
+        let mut rtld = try!(SharedObject::from_memory(SONAME, self.load_bias, self.phdrs));
+        if let Some(interpreter) = self.interpreter() {
+            rtld.path = interpreter.to_string();
+        }
+        self.link_map.push(rtld);
+        let idx = self.link_map.len() - 1;
+        self.rtld = Some (idx);
+        Ok (idx)
+    }
+
+    #[inline]
+    fn is_rtld(&self, idx: usize) -> bool {
+        self.rtld == Some (idx)
+    }
+
+    /// Whether `name`, a `DT_NEEDED` entry or the file a symbol version is needed from, names the dynamic linker, i.e., one of `RTLD_SONAMES`, or whatever the executable's `PT_INTERP` is called; only the file names are compared, since the `PT_INTERP` is a path and the others usually aren't
+    fn is_rtld_name(&self, name: &str) -> bool {
+        let basename = |path: &'process str| path.rsplit('/').next().unwrap_or(path);
+        let name = name.rsplit('/').next().unwrap_or(name);
+        RTLD_SONAMES.contains(&name) || self.interpreter().map(basename) == Some (name)
+    }
+
+    /// Loads the `DT_NEEDED` of every object from `next` on, and then theirs, and so on, appending each new object to the link map; this is what makes the global scope breadth first.
+    /// In ldd mode, a library which can't be found is added to `not_found`, paired with how many objects were loaded before it, instead of being an error
+    fn load_dependencies(&mut self, mut next: usize, not_found: &mut Vec<(usize, String)>) -> Result<(), String> {
+        while next < self.link_map.len() {
+            let libs: Vec<String> = self.link_map[next].libs.iter().map(|lib| lib.to_string()).collect();
+            let mut needed = Vec::with_capacity(libs.len());
+            for lib in &libs {
+                match try!(self.load(lib, next)) {
+                    Some (idx) => needed.push(idx),
+                    // ldd lists everything it couldn't find, instead of stopping at the first
+                    None if self.config.trace_loaded_objects => {
+                        if !not_found.iter().any(|&(_, ref name)| name == lib) {
+                            not_found.push((self.link_map.len(), lib.to_string()));
                         }
-                        break
                     },
-                    _ => (),
+                    None => return Err(format!("<dryad> could not find {} needed by {}", lib, self.link_map[next].name))
                 }
             }
-            if !found {
-                return Err(format!("<dryad> could not find {} in {:?}", &soname, self.config.library_path))
-            }
+            self.link_map[next].needed = needed;
+            next += 1;
         }
+        Ok (())
+    }
+
+    /// Gives every object from `first` on a `LinkNode`, which its GOT[1] points at, for lazy binding
+    fn add_nodes(&mut self, first: usize) {
+        for idx in first..self.link_map.len() {
+            self.nodes.push(Box::new(LinkNode { idx: idx }));
+        }
+    }
 
+    /// Loads the `LD_PRELOAD` or `/etc/ld.so.preload` object `name`, which can be a path or a soname; like ld-so, one which can't be found is reported and skipped, rather than being fatal.
+    /// When `restricted`, i.e., it came from `LD_PRELOAD` and we're running `AT_SECURE`, only setuid libraries in the default dirs are allowed
+    fn preload(&mut self, name: &str, restricted: bool) -> Result<(), String> {
+        if self.find_loaded(name).is_some() {
+            return Ok (())
+        }
+        let paths = if restricted {
+            search_path::DEFAULT_DIRS.iter().map(|dir| format!("{}/{}", dir, name)).collect()
+        } else {
+            self.search(name, 0)
+        };
+        let first = self.link_map.len();
+        match try!(self.open(name, paths, restricted)) {
+            Some (idx) if idx >= first => self.link_map[idx].loader = Some (0),
+            Some (_) => (),
+            None => { error!("<dryad> ERROR: object '{}' cannot be preloaded: ignored", name); },
+        }
         Ok (())
     }
 
-    // TODO: holy _god_ is this slow; no wonder they switched to a bloom filter.  fix this with proper symbol finding, etc.
-    // HACK for testing, performs shitty linear search using the so's `find` method, which is compounded by * num so's (* number of total relocations in this binary group... ouch)
-    // fn find_symbol(&self, name: &str) -> Option<&sym::Sym> {
-    fn find_symbol(&self, name: &str) -> Option<u64> {
-        for so in &self.link_map {
-            //println!("<dryad> searching {} for {}", so.name, name);
-            let addr = so.find(name);
-            if addr != None {
-                return addr
+    /// Prints every loaded object, in load order, the way `ldd` does, along with the libraries in `not_found`, each of which is paired with how many objects were loaded before we failed to find it
+    fn trace(&self, not_found: &[(usize, String)]) {
+        if self.vdso != 0 {
+            println!("\tlinux-vdso.so.1 (0x{:016x})", self.vdso);
+        }
+        let mut missing = 0;
+        for (i, so) in self.link_map.iter().enumerate() {
+            while missing < not_found.len() && not_found[missing].0 <= i {
+                println!("\t{} => not found", not_found[missing].1);
+                missing += 1;
+            }
+            if i == 0 || self.is_rtld(i) {
+                continue
+            }
+            if so.name.contains('/') {
+                println!("\t{} (0x{:016x})", so.path, so.map_begin);
+            } else {
+                println!("\t{} => {} (0x{:016x})", so.name, so.path, so.map_begin);
+            }
+        }
+        for &(_, ref name) in &not_found[missing..] {
+            println!("\t{} => not found", name);
+        }
+        if let Some(interpreter) = self.interpreter() {
+            println!("\t{} (0x{:016x})", interpreter, self.base);
+        }
+    }
+
+    /// The executable's `PT_INTERP`, i.e., the path it asked to be linked by
+    fn interpreter(&self) -> Option<&'process str> {
+        let executable = &self.link_map[0];
+        executable.phdrs.iter()
+            .find(|phdr| phdr.p_type == program_header::PT_INTERP)
+            .map(|phdr| utils::as_str((phdr.p_vaddr + executable.load_bias) as *const u8))
+    }
+
+    /// Reports, on stderr, every symbol referenced by a relocation which no loaded object defines, like `ldd -d`; the PLT relocations are only checked with `LD_BIND_NOW` too, like `ldd -r`, since otherwise they'd be bound lazily.
+    /// An undefined weak symbol is allowed to stay undefined, so isn't reported
+    fn report_undefined(&self) {
+        for (i, so) in self.link_map.iter().enumerate() {
+            if self.is_rtld(i) {
+                continue
+            }
+            for sym in self.undefined(i) {
+                let name = &so.strtab[so.symtab[sym].st_name as usize];
+                match so.versions.version(sym) {
+                    Some (version) => { let _ = writeln!(io::stderr(), "undefined symbol: {}, version {}\t({})", name, version.name, so.path); },
+                    None => { let _ = writeln!(io::stderr(), "undefined symbol: {}\t({})", name, so.path); },
+                }
+            }
+        }
+    }
+
+    /// Returns the symbol index of every symbol the relocations of the object at `idx` reference, each paired with the class of relocation it's bound for, once; the PLT relocations are only included if the object binds them now
+    fn symbol_references(&self, idx: usize) -> Vec<(usize, resolve::Class)> {
+        let so = &self.link_map[idx];
+        let (pltrelatab, pltreltab): (&[rela::Rela], &[rel::Rel]) = if self.binds_now(so) { (so.pltrelatab, so.pltreltab) } else { (&[], &[]) };
+        let relas = so.relatab.iter().chain(pltrelatab.iter()).map(|rela| rela.r_info);
+        let rels = so.reltab.iter().chain(pltreltab.iter()).map(|rel| rel.r_info);
+        let mut references = Vec::new();
+        for r_info in relas.chain(rels) {
+            let reference = (rela::r_sym(r_info) as usize, resolve::Class::of(rela::r_type(r_info)));
+            if reference.0 != 0 && !references.contains(&reference) {
+                references.push(reference);
+            }
+        }
+        references
+    }
+
+    /// Returns the symbol index of every strong reference in the relocations of the object at `idx` which nothing in its scope defines; the PLT relocations are only checked if the object binds them now
+    fn undefined(&self, idx: usize) -> Vec<usize> {
+        let so = &self.link_map[idx];
+        let mut undefined = Vec::new();
+        for (sym, class) in self.symbol_references(idx) {
+            if undefined.contains(&sym) {
+                continue
+            }
+            let symbol = &so.symtab[sym];
+            if !sym::is_import(symbol) || resolve::is_weak_reference(symbol) {
+                continue
+            }
+            if resolve::resolve(&self.link_map, idx, sym, class).is_none() {
+                undefined.push(sym);
+            }
+        }
+        undefined
+    }
+
+    /// Checks every version each object needs (its `DT_VERNEED`) is defined by the dependency it's needed from, and returns an error for each one which isn't, like glibc's "version `GLIBC_2.99' not found".
+    /// A weak version reference, or one to an object which wasn't loaded (which is already an error), is never an error. Only the objects from `from` on are checked
+    fn missing_versions(&self, from: usize) -> Vec<String> {
+        let mut missing = Vec::new();
+        for (i, so) in self.link_map.iter().enumerate().skip(from) {
+            if so.unloaded || self.is_rtld(i) {
+                continue
+            }
+            for needed in so.versions.needed() {
+                let file = needed.file.as_ref().map(|file| file.as_str()).unwrap_or("");
+                // dryad defines no versions, and its unversioned `__tls_get_addr` and `dl*` functions satisfy any of ld.so's; every other symbol of ld.so's, e.g., glibc's `GLIBC_PRIVATE` internals, is simply undefined
+                if self.is_rtld_name(file) {
+                    continue
+                }
+                let definer = match so.needed.iter().map(|&dep| &self.link_map[dep]).find(|dep| dep.name == file || dep.soname == file) {
+                    Some (definer) => definer,
+                    None => match self.find_loaded(file) {
+                        Some (idx) => &self.link_map[idx],
+                        None => continue,
+                    },
+                };
+                debug!(debug::VERSIONS, "checking for version `{}' in file {} [{}] required by file {}", needed.name, definer.path, file, so.name);
+                if definer.versions.defines(&needed.name) {
+                    continue
+                }
+                if needed.flags & version::VER_FLG_WEAK != 0 {
+                    debug!(debug::VERSIONS, "weak version `{}' not found in file {} required by file {}", needed.name, definer.path, so.name);
+                    continue
+                }
+                missing.push(format!("<dryad> {}: version `{}' not found (required by {})", definer.path, needed.name, so.path));
             }
         }
+        missing
+    }
+
+    /// Returns the local scope of the object at `idx`: the object itself, followed by its dependencies, breadth first, each at most once
+    fn local_scope(&self, idx: usize) -> Vec<usize> {
+        let mut seen = vec![false; self.link_map.len()];
+        let mut scope = vec![idx];
+        seen[idx] = true;
+        let mut next = 0;
+        while next < scope.len() {
+            for &dep in &self.link_map[scope[next]].needed {
+                if !seen[dep] {
+                    seen[dep] = true;
+                    scope.push(dep);
+                }
+            }
+            next += 1;
+        }
+        scope
+    }
 
-        None
+    /// Returns the TLS module and the offset within its block for a TLS relocation in the object at index `idx` against the symbol at index `sym`, named `name`;
+    /// the null symbol (index 0) and local symbols refer to the object's _own_ TLS block, and anything else is resolved
+    fn resolve_tls(&self, idx: usize, sym: usize, name: &str) -> Option<(tls::TlsModule, u64)> {
+        let symbol = &self.link_map[idx].symtab[sym];
+        if name == "" || sym::st_bind(symbol.st_info) == sym::STB_LOCAL {
+            self.tls.module(idx).map(|module| (module, symbol.st_value))
+        } else if let Some((definer, sym)) = resolve::resolve(&self.link_map, idx, sym, resolve::Class::Address) {
+            self.tls.module(definer).map(|module| (module, sym.st_value))
+        } else {
+            None
+        }
     }
 
     /// Following the steps below, the dynamic linker and the program "cooperate"
@@ -396,141 +984,326 @@ impl<'process> Linker<'process> {
     fn prepare_got<'a> (&self, idx: usize, pltgot: *const u64, name: &'a str) {
 //        println!("preparing got for: {:?}", so);
         if pltgot.is_null() {
-            println!("<dryad> empty pltgot for {}", name);
+            debug!(debug::RELOC, "empty pltgot for {}", name);
             return
         }
-        // TODO: I had to go and break this working version to use a custom vector of LinkData because there's something wrong with me.
         unsafe {
-            // TODO: fix the mut borrows here
             // got[0] == the program's address of the _DYNAMIC array, equal to address of the PT_DYNAMIC.ph_vaddr + load_bias
             // got[1] == "is the pointer to a data structure that the dynamic linker manages. This data structure is a linked list of nodes corresponding to the symbol tables for each shared library linked with the program. When a symbol is to be resolved by the linker, this list is traversed to find the appropriate symbol."
             let second_entry = pltgot.offset(1) as *mut u64;
             // got[2] == the dynamic linker's runtime symbol resolver
             let third_entry = pltgot.offset(2) as *mut u64;
 
-            /* if we use a vector of link map data and an index as the other pair for _who_ we are then this probably works better than rewinding the fucking link map every time, except we allocate a pair every time also...
-            let pair = Box::new((name as *const str, &*(self.working_set) as *const HashMap<String, SharedObject>));
-            let pair_ptr: *mut (*const str, *const HashMap<String, SharedObject>) = Box::into_raw(pair);
-            *second_entry = pair_ptr as u64;
-            */
-            let len = self.link_map.len();
-            let rdvz = Box::new((idx, self.link_map.as_slice(), len));
-            println!("rdvz idx {} with len {}", idx, len);
-            *second_entry = Box::into_raw(rdvz) as u64;
+            *second_entry = &*self.nodes[idx] as *const LinkNode as u64;
             *third_entry = _dryad_resolve_symbol as u64;
-            println!("<dryad> finished got setup for {} GOT[1] = {:#x} GOT[2] = {:#x}", name, *second_entry, *third_entry);
+            debug!(debug::RELOC, "finished got setup for {} GOT[1] = {:#x} GOT[2] = {:#x}", name, *second_entry, *third_entry);
         }
     }
 
-    #[no_mangle]
-    fn resolve_with_ifunc (&self, addr: u64) -> u64 {
-        unsafe {
-            let ifunc = mem::transmute::<u64, (fn() -> *const u64)>(addr);
-            let res = ifunc();
-            println!("ifunc says: {:?}", res);
-            res as u64
+    /// Fills the two word TLS descriptor at `reloc` for the `R_X86_64_TLSDESC` relocation `rela` in the object at index `idx`; these can show up in either relocation table
+    fn relocate_tlsdesc (&self, idx: usize, object: &SharedObject, rela: &rela::Rela, sym: usize, name: &str, reloc: *mut u64) -> Result<bool, String> {
+        if let Some((module, value)) = self.resolve_tls(idx, sym, name) {
+            let (resolver, arg) = tls::descriptor(&module, (value as i64 + rela.r_addend) as u64);
+            unsafe {
+                *reloc = resolver;
+                *reloc.offset(1) = arg;
+            }
+            Ok (true)
+        } else {
+            Err(no_tls_module(object, rela, name))
         }
     }
 
-    // TODO: rela::R_X86_64_GLOB_DAT => this is a symbol resolution and requires full link map data, and _cannot_ be done before everything is relocated
-    fn relocate_got (&self, idx: usize, object: &SharedObject) {
-        let symtab = &object.symtab;
-        let strtab = &object.strtab;
+    /// Applies the relocation `rela` in the object at index `idx`; every type which can show up in a dynamic relocation table is handled here, see the computations in `rela.rs`, except for lazily bound `R_X86_64_JUMP_SLOT`s, the `R_X86_64_COPY`s, which wait for `relocate_copy`, and the `R_X86_64_IRELATIVE`s, which wait for `relocate_irelative`.
+    /// Returns whether the relocation was applied; a strong reference to a symbol nothing defines is an error, as is a TLS relocation without a TLS module, or a type we don't know, since skipping either just leaves garbage for the program to crash on somewhere else entirely
+    fn relocate (&self, idx: usize, object: &SharedObject, rela: &rela::Rela) -> Result<bool, String> {
+        let typ = rela::r_type(rela.r_info);
+        let sym = rela::r_sym(rela.r_info) as usize; // index into the sym table
+        let name = &object.strtab[object.symtab[sym].st_name as usize];
         let bias = object.load_bias;
+        let addend = rela.r_addend;
+        let place = rela.r_offset + bias;
+        let reloc = place as *mut u64;
+        match typ {
+            rela::R_X86_64_NONE => Ok (false),
+            // B + A
+            rela::R_X86_64_RELATIVE | rela::R_X86_64_RELATIVE64 => {
+                unsafe { *reloc = (addend + bias as i64) as u64; }
+                Ok (true)
+            },
+            // the resolver may itself use anything else in the object, so these wait for `relocate_irelative`
+            rela::R_X86_64_IRELATIVE => Ok (false),
+            // S, S + A, S + A - P, and Z + A, where S and Z are the address and size of the definition
+            rela::R_X86_64_GLOB_DAT | rela::R_X86_64_JUMP_SLOT |
+            rela::R_X86_64_64 | rela::R_X86_64_PC64 | rela::R_X86_64_SIZE64 |
+            rela::R_X86_64_32 | rela::R_X86_64_32S | rela::R_X86_64_PC32 | rela::R_X86_64_SIZE32 => {
+                let (_, value, size) = match resolve::resolve_definition(&self.link_map, idx, sym, resolve::Class::of(typ)) {
+                    Some (definition) => definition,
+                    // a weak reference nothing defines resolves to 0, so this is a strong one
+                    None => return Err(undefined_symbol(&self.link_map, object, sym).to_string()),
+                };
+                let value = value as i64;
+                let size = size as i64;
+                let place = place as i64;
+                unsafe {
+                    match typ {
+                        rela::R_X86_64_GLOB_DAT | rela::R_X86_64_JUMP_SLOT => *reloc = value as u64,
+                        rela::R_X86_64_64 => *reloc = (value + addend) as u64,
+                        rela::R_X86_64_PC64 => *reloc = (value + addend - place) as u64,
+                        rela::R_X86_64_SIZE64 => *reloc = (size + addend) as u64,
+                        rela::R_X86_64_32 => try!(relocate32(object, rela, value + addend, false)),
+                        rela::R_X86_64_SIZE32 => try!(relocate32(object, rela, size + addend, false)),
+                        rela::R_X86_64_32S => try!(relocate32(object, rela, value + addend, true)),
+                        _ => try!(relocate32(object, rela, value + addend - place, true)),
+                    }
+                }
+                Ok (true)
+            },
+            // the module id of the TLS block defining the symbol
+            rela::R_X86_64_DTPMOD64 => {
+                if let Some((module, _)) = self.resolve_tls(idx, sym, name) {
+                    unsafe { *reloc = module.id as u64; }
+                    Ok (true)
+                } else {
+                    Err(no_tls_module(object, rela, name))
+                }
+            },
+            // the symbol's offset within its module's TLS block
+            rela::R_X86_64_DTPOFF64 | rela::R_X86_64_DTPOFF32 => {
+                if let Some((_, value)) = self.resolve_tls(idx, sym, name) {
+                    let offset = value as i64 + addend;
+                    if typ == rela::R_X86_64_DTPOFF64 {
+                        unsafe { *reloc = offset as u64; }
+                    } else {
+                        try!(unsafe { relocate32(object, rela, offset, true) });
+                    }
+                    Ok (true)
+                } else {
+                    Err(no_tls_module(object, rela, name))
+                }
+            },
+            // the symbol's (negative) offset from the thread pointer, in the static TLS area
+            rela::R_X86_64_TPOFF64 | rela::R_X86_64_TPOFF32 => {
+                if let Some((module, value)) = self.resolve_tls(idx, sym, name) {
+                    if let Some(offset) = module.offset {
+                        let offset = value as i64 + addend - offset as i64;
+                        if typ == rela::R_X86_64_TPOFF64 {
+                            unsafe { *reloc = offset as u64; }
+                        } else {
+                            try!(unsafe { relocate32(object, rela, offset, true) });
+                        }
+                        Ok (true)
+                    } else {
+                        Err(format!("<dryad> {}: {} is not in the static TLS area, but offset {:#x} needs it for {}", object.name, name, rela.r_offset, rela::type_to_str(typ)))
+                    }
+                } else {
+                    Err(no_tls_module(object, rela, name))
+                }
+            },
+            rela::R_X86_64_TLSDESC => self.relocate_tlsdesc(idx, object, rela, sym, name, reloc),
+            // the library data being copied may itself need relocating first, so these wait for `relocate_copy`; only the executable can have them
+            rela::R_X86_64_COPY if idx == 0 => Ok (false),
+            _ => Err(format!("<dryad> {}: unsupported relocation type {} ({}) at offset {:#x}", object.name, rela::type_to_str(typ), typ, rela.r_offset))
+        }
+    }
+
+    /// Applies every relocation in the object's `DT_RELA` and `DT_REL` tables, and then prepares its GOT for lazy binding
+    fn relocate_got (&self, idx: usize, object: &SharedObject) -> Result<(), String> {
         let mut count = 0;
         for rela in object.relatab {
-            let typ = rela::r_type(rela.r_info);
-            let sym = rela::r_sym(rela.r_info); // index into the sym table
-            let symbol = &symtab[sym as usize];
-            let name = &strtab[symbol.st_name as usize];
-            let reloc = (rela.r_offset + bias) as *mut u64;
-            // TODO: remove this print, misleading on anything other than RELATIVE relocs
-//            println!("relocating {} {}({:?}) with addend {:x} to {:x}", name, (rela::type_to_str(typ)), reloc, rela.r_addend, (rela.r_addend + bias as i64));
-            match typ {
-                // B + A
-                rela::R_X86_64_RELATIVE | rela::R_X86_64_TPOFF64 => {
-                    // set the relocations address to the load bias + the addend
-                    unsafe { *reloc = (rela.r_addend + bias as i64) as u64; }
-                    count += 1;
-                },
-                // S TODO: this is a symbol resolution and requires
-                rela::R_X86_64_GLOB_DAT => {
-                    // resolve symbol;
-                    // 1. start with exe, then next in needed, then next until symbol found
-                    // 2. use gnu_hash with symbol name to get sym info
-                    if let Some(symbol) = self.find_symbol(name) {
-                        unsafe { *reloc = symbol; }
-                    }
-                    count += 1;
-                },
-                // S + A
-                rela::R_X86_64_64 => {
-                    // TODO: this is inaccurate because find_symbol is inaccurate
-                    if let Some(symbol) = self.find_symbol(name) {
-                        unsafe { *reloc = (rela.r_addend + symbol as i64) as u64; }
+            if try!(self.relocate(idx, object, rela)) {
+                count += 1;
+            }
+        }
+        for rel in object.reltab {
+            let rela = unsafe { rel::to_rela(rel, object.load_bias) };
+            if try!(self.relocate(idx, object, &rela)) {
+                count += 1;
+            }
+        }
+
+        debug!(debug::STATISTICS, "relocated {} symbols in {}", count, &object.name);
+
+        self.prepare_got(idx, object.pltgot, &object.name);
+        Ok (())
+    }
+
+    /// Processes the executable's `R_X86_64_COPY` relocations, which copy the initial value of a library's data object, e.g., `stdout` or `environ`, into space reserved for it in the executable;
+    /// since the executable is first in the global scope, every other reference, including the library's own, then binds to the executable's copy.
+    /// The copy has to see the library's data after _its_ relocations, so this runs once every library is relocated; a symbol nothing else defines is an error, since the executable's copy would otherwise just be zeroes
+    fn relocate_copy (&self, object: &SharedObject) -> Result<(), String> {
+        let mut count = 0;
+        for rela in object.relatab {
+            if rela::r_type(rela.r_info) != rela::R_X86_64_COPY {
+                continue
+            }
+            let sym = rela::r_sym(rela.r_info) as usize;
+            let symbol = &object.symtab[sym];
+            let name = &object.strtab[symbol.st_name as usize];
+            let version = object.versions.version(sym);
+            let reloc = (rela.r_offset + object.load_bias) as *mut u8;
+            // the executable's copy is itself a definition, and the first one in scope, so the search starts after it
+            match resolve::lookup(&self.link_map[1..], name, version, resolve::Class::Address) {
+                Some ((definer, definition)) => {
+                    let definer = &self.link_map[definer + 1];
+                    if definition.st_size > symbol.st_size {
+                        error!("<dryad> Warning, symbol `{}' has different size in shared object {}, consider re-linking", name, definer.name);
                     }
+                    let size = cmp::min(definition.st_size, symbol.st_size) as usize;
+                    let src = (definition.st_value + definer.load_bias) as *const u8;
+                    debug!(debug::RELOC, "copying {} bytes of {} from {} at {:?} to {:?}", size, name, definer.name, src, reloc);
+                    unsafe { ptr::copy_nonoverlapping(src, reloc, size); }
                     count += 1;
                 },
-                /*
-                rela::R_X86_64_TPOFF64 => {
-                    unsafe { *reloc = (rela.r_addend + bias as i64) as u64; }
-                    count += 1;
-                },
-                */
-                // TODO: add erro checking
-                _ => ()
+                None => return Err(undefined_symbol(&self.link_map, object, sym).to_string()),
             }
         }
+        debug!(debug::STATISTICS, "copied {} symbols into {}", count, object.name);
+        Ok (())
+    }
 
-        println!("<dryad> relocated {} symbols in {}", count, &object.name);
+    /// Processes the object's `R_X86_64_IRELATIVE` relocations, in any of its tables, i.e., `(B + A)()`, where `B + A` is the address of an IFUNC resolver local to the object;
+    /// since the resolver can read the object's data, or call through its GOT, this must only run once all of the object's other relocations have been processed
+    fn relocate_irelative (&self, object: &SharedObject) {
+        let bias = object.load_bias;
+        let mut count = 0;
+        let reltab: Vec<rela::Rela> = object.reltab.iter().chain(object.pltreltab.iter())
+            .filter(|rel| rel::r_type(rel.r_info) == rela::R_X86_64_IRELATIVE)
+            .map(|rel| unsafe { rel::to_rela(rel, bias) }).collect();
+        for rela in object.relatab.iter().chain(object.pltrelatab.iter()).chain(reltab.iter()) {
+            if rela::r_type(rela.r_info) != rela::R_X86_64_IRELATIVE {
+                continue
+            }
+            let resolver = (rela.r_addend + bias as i64) as u64;
+            let reloc = (rela.r_offset + bias) as *mut u64;
+            unsafe { *reloc = resolve::ifunc(resolver); }
+            count += 1;
+        }
+        debug!(debug::STATISTICS, "relocated {} ifuncs in {}", count, object.name);
+    }
 
-        self.prepare_got(idx, object.pltgot, &object.name);
+    /// The binding policy for the object's PLT: it's bound eagerly if it was linked `-z now` (see `SharedObject::is_bind_now`), whatever the environment says, or if `LD_BIND_NOW` is set; otherwise lazily
+    fn binds_now (&self, object: &SharedObject) -> bool {
+        object.is_bind_now() || self.config.bind_now
     }
 
-    fn relocate_plt (&self, object: &SharedObject, is_executable: bool) {
+    fn relocate_plt (&self, idx: usize, object: &SharedObject) -> Result<(), String> {
 
-        let symtab = &object.symtab;
-        let strtab = &object.strtab;
         let bias = object.load_bias;
         let mut count = 0;
 
-        // TODO: if we split code starting here into two functions, and loop twice over the dependencies, 1st time calling above for GOT and second below for PLT in each loop, then i believe ifunc's won't die once i can properly call other functions dynamically; the same dependency chain might exist in the GOT too though when resolving GLOB_DAT and 64 references, must think about this
-        // TODO: or the SO has the DT_BIND_NOW, and also some shit in the flags
-        if is_executable || !self.config.bind_now { return }
+        // a `-z now` object's PLT slots are inside its RELRO segment, which is read-only by the time anything could call through them, so they have to be bound now, executable or not
+        let eager = self.binds_now(object);
+        debug!(debug::BINDINGS, "binding {} {}", object.name, if eager { "now" } else { "lazily" });
 
         // x86-64 ABI, pg. 78:
         // > Much as the global offset table redirects position-independent address calculations
         // > to absolute locations, the procedure linkage table redirects position-independent
         // > function calls to absolute locations.
-        for rela in object.pltrelatab {
-            let typ = rela::r_type(rela.r_info);
-            let sym = rela::r_sym(rela.r_info); // index into the sym table
-            let symbol = &symtab[sym as usize];
-            let name = &strtab[symbol.st_name as usize];
-            let reloc = (rela.r_offset + bias) as *mut u64;
-            match typ {
-                rela::R_X86_64_JUMP_SLOT => {
-                    if let Some(symbol_address) = self.find_symbol(name) {
-//                        println!("resolving {} to {:#x}", name, symbol_address);
-                        unsafe { *reloc = symbol_address; }
+        // the PLT relocations are either all rela or all rel, depending on `DT_PLTREL`, and the rel ones are applied as rela with their implicit addends
+        let pltreltab: Vec<rela::Rela> = object.pltreltab.iter().map(|rel| unsafe { rel::to_rela(rel, bias) }).collect();
+        for rela in object.pltrelatab.iter().chain(pltreltab.iter()) {
+            match rela::r_type(rela.r_info) {
+                // until it's bound, the slot holds the link time address of its PLT entry's `pushq`, which falls through to `_dryad_resolve_symbol`
+                rela::R_X86_64_JUMP_SLOT if !eager => {
+                    let reloc = (rela.r_offset + bias) as *mut u64;
+                    unsafe { *reloc += bias; }
+                },
+                // we don't resolve TLS descriptors lazily, so everything else is applied regardless of binding, or deferred, like `R_X86_64_IRELATIVE`
+                _ => {
+                    if try!(self.relocate(idx, object, rela)) {
                         count += 1;
-                    } else {
-                        println!("<dryad> Warning, no resolution for {}", name);
                     }
-                },
-                // fun @ (B + A)()
-                rela::R_X86_64_IRELATIVE => {
-                    let addr = rela.r_addend + bias as i64;
-                    println!("IRELATIVE: bias: {:#x} addend: {:#x} addr: {:#x}", bias, rela.r_addend, addr);
-                    // TODO: just inline this call here, it's so simple, doesn't need a function
-                    unsafe { *reloc = self.resolve_with_ifunc(addr as u64); }
-                    count += 1;
-                },
-                // TODO: add error checking
-                _ => ()
+                }
+            }
+        }
+        debug!(debug::STATISTICS, "relocated {} plt symbols in {}", count, object.name);
+        Ok (())
+    }
+
+    /// Depth first, post order walk of the object at `idx` and its `DT_NEEDED`, so that every object comes after all of its dependencies
+    fn visit_dependencies (&self, idx: usize, visited: &mut Vec<bool>, order: &mut Vec<usize>) {
+        if visited[idx] {
+            return
+        }
+        // marking before recursing breaks dependency cycles; glibc just picks an order for these too
+        visited[idx] = true;
+        for &dep in &self.link_map[idx].needed {
+            self.visit_dependencies(dep, visited, order);
+        }
+        order.push(idx);
+    }
+
+    /// Returns the link map indices of `objects` in the order their constructors should run, i.e., dependencies before their dependents; anything else they depend on is assumed to be initialized already.
+    /// Dryad itself initialized itself long ago, so it's never in the order
+    fn init_order (&self, objects: &[usize]) -> Vec<usize> {
+        let mut visited = vec![true; self.link_map.len()];
+        for &idx in objects {
+            visited[idx] = self.is_rtld(idx);
+        }
+        let mut order = Vec::with_capacity(objects.len());
+        for &idx in objects {
+            self.visit_dependencies(idx, &mut visited, &mut order);
+        }
+        order
+    }
+
+    /// Returns the `DT_INIT` and then the `DT_INIT_ARRAY` entries of every library in `order`, in the order they're called;
+    /// the executable's own constructors are left to libc, which its `_start` passes them to
+    fn initializers (&self, order: &[usize]) -> Vec<u64> {
+        let mut initializers = Vec::new();
+        for &idx in order {
+            if idx == 0 {
+                continue
+            }
+            let so = &self.link_map[idx];
+            if so.link_info.init != 0 {
+                initializers.push(so.link_info.init);
+            }
+            for init in so.init_array() {
+                if is_valid_function(*init) {
+                    initializers.push(*init);
+                }
+            }
+        }
+        initializers
+    }
+
+    /// Returns the fini array entries, backwards, followed by `DT_FINI`, for every object in the reverse of `order`
+    fn finalizers (&self, order: &[usize]) -> Vec<u64> {
+        let mut finalizers = Vec::new();
+        for &idx in order.iter().rev() {
+            let so = &self.link_map[idx];
+            for fini in so.fini_array().iter().rev() {
+                if is_valid_function(*fini) {
+                    finalizers.push(*fini);
+                }
+            }
+            if so.link_info.fini != 0 {
+                finalizers.push(so.link_info.fini);
             }
         }
-        println!("<dryad> relocate plt: {} symbols for {}", count, object.name);
+        finalizers
+    }
+
+    /// Makes the object's `PT_GNU_RELRO` segment read-only; must only be called once _all_ of its relocations have been processed.
+    /// A lazily bound object's PLT slots have to stay writable, so if its RELRO segment covers its PLT GOT anyway, only the pages before it are protected
+    fn protect_relro (&self, object: &SharedObject) -> Result<(), String> {
+        if let Some((start, mut len)) = object.relro() {
+            let pltgot = object.pltgot as u64;
+            if !self.binds_now(object) && !object.pltgot.is_null() && pltgot >= start && pltgot < start + len as u64 {
+                len = (utils::page::page_start(pltgot) - start) as usize;
+                debug!(debug::RELOC, "RELRO of {} covers its lazily bound PLT GOT at {:#x}, only protecting {:#x} - {:#x}", object.name, pltgot, start, start + len as u64);
+                if len == 0 {
+                    return Ok (())
+                }
+            }
+            if unsafe { mmap::mprotect(start as *const u64, len, mmap::PROT_READ) } != 0 {
+                return Err(format!("<dryad> could not mprotect RELRO {:#x} - {:#x} of {}, aborting execution", start, start + len as u64, object.name))
+            }
+            debug!(debug::RELOC, "RELRO {:#x} - {:#x} for {}", start, start + len as u64, object.name);
+        }
+        Ok (())
     }
     
     /// Main staging point for linking the executable dryad received
@@ -538,8 +1311,9 @@ impl<'process> Linker<'process> {
     /// 1. First builds the executable and then all the shared object dependencies and joins the result
     /// 2. Then, creates the link map, and then relocates all the shared object dependencies and joins the result
     /// 3. Finally, relocates the executable, and then transfers control
+    /// Returns the constructors, in the order `run_init` should call them
     #[no_mangle]
-    pub fn link(&mut self, block: &kernel_block::KernelBlock) -> Result<(), String> {
+    pub fn link(&mut self, block: &kernel_block::KernelBlock) -> Result<Vec<u64>, String> {
 
         /* Fun Fact: uncomment this for ridiculous disaster: runs fine when links itself, but not when it links an executable, because hell
         let v = vec![1, 2, 3, 4];
@@ -560,39 +1334,25 @@ impl<'process> Linker<'process> {
         */
 
         // build executable
-        println!("BEGIN EXE LINKING");
+        debug!(debug::FILES, "linking {}", utils::as_str(block.argv[0]));
         let name = utils::as_str(block.argv[0]);
         let phdr_addr = block.getauxval(auxv::AT_PHDR).unwrap();
         let phnum  = block.getauxval(auxv::AT_PHNUM).unwrap();
-        let image = try!(SharedObject::from_executable(name, phdr_addr, phnum as usize));
-        println!("Main Image:\n  {:#?}", &image);
+        let mut image = try!(SharedObject::from_executable(name, phdr_addr, phnum as usize));
+        // the executable's $ORIGIN is where the kernel found it
+        if let Some(execfn) = block.getauxval(auxv::AT_EXECFN) {
+            if execfn != 0 {
+                image.path = utils::as_str(execfn as *const u8).to_string();
+            }
+        }
+        debug!(debug::FILES, "main image:\n  {:#?}", &image);
 
         // 1. load all
 
         // TODO: transfer ownership of libs (or allocate) to the linker, so it can be parallelized
         // this is the only obvious candidate for parallelization, and it's dubious at best... but large binaries spend 20% of time loading and 80% on relocation
-        self.link_map_order.extend(image.libs.iter().map(|s| s.to_string()));
-
-        for lib in &image.libs {
-            try!(self.load(lib));
-        }
-
-        self.link_map_order.dedup(); // arrrrggggh this doesn't arbitrarily reduce duplicates so test/snappy dies earlier
-        println!("LINK MAP ORDER: {:#?}", self.link_map_order);
 
-        self.link_map.reserve_exact(self.link_map_order.len()+1);
-        self.link_map.push(image);
-        for soname in &self.link_map_order {
-            println!("remove: {:#?}", soname);
-            let so = self.working_set.remove(soname).unwrap();
-            self.link_map.push(so);
-        }
-        println!("working set is drained: {}", self.working_set.len() == 0);
-        // <join>
-        // 2. relocate all
-        // TODO: after _all_ SharedObject have been loaded, it is safe to relocate if we stick to ELF symbol search rule of first search executable, then in each of DT_NEEDED in order, then deps of first DT_NEEDED, and if not found, then deps of second DT_NEEDED, etc., i.e., breadth-first search.  Why this is allowed to continue past the executable's _OWN_ dependency list is anyone's guess; a penchant for chaos perhaps?
-
-        // SCOPE is resolved breadth first by ld-so and flattened to a single search list (more or less)
+        // the scope is resolved breadth first, like ld-so, and flattened to a single search list; every object's DT_NEEDED are loaded, in order, before any of their own dependencies
         // exe
         // |_ libfoo
         // |_ libbar
@@ -607,32 +1367,424 @@ impl<'process> Linker<'process> {
         // |_
         //
         // is reduced to [exe, libfoo, libbar, libbaz, libderp, libslerp, libmerp]
+        self.link_map.push(image);
+
+        // preloaded objects come right after the executable, and before any of its dependencies, so their definitions interpose on everything else
+        let secure = self.config.secure;
+        let preload = self.config.preload.to_owned();
+        for name in preload {
+            try!(self.preload(name, secure));
+        }
+        if let Ok (mut fd) = File::open(PRELOAD_PATH) {
+            let mut preload = String::new();
+            if fd.read_to_string(&mut preload).is_ok() {
+                for name in preload.split(|c: char| c.is_whitespace() || c == ':').filter(|name| *name != "") {
+                    try!(self.preload(name, false));
+                }
+            }
+        }
+
+        try!(self.rtld());
 
-        // TODO: determine ld-so's relocation order (_not_ equivalent to it's search order, which is breadth first from needed libs)
-        // Because gnu_ifuncs essentially execute arbitrary code, including calling into the GOT, if the GOT isn't setup and relative relocations, for example, haven't been processed in the binary which has the reference, we're doomed.  Example is a libm ifunc (after matherr) for `__exp_finite` that calls `__get_cpu_features` which resides in libc.
+        let mut not_found: Vec<(usize, String)> = Vec::new();
+        try!(self.load_dependencies(0, &mut not_found));
+        self.startup = self.link_map.len();
+        // everything loaded at startup is in the global scope, and is never unloaded
+        for so in self.link_map.iter_mut() {
+            so.global = true;
+            so.flags_1 |= dyn::DF_1_NODELETE;
+        }
+
+        for idx in 0..self.link_map.len() {
+            let scope = self.local_scope(idx);
+            self.link_map[idx].scope = scope;
+        }
+        debug!(debug::SCOPES, "global scope: {:?}", self.link_map.iter().map(|so| &so.name).collect::<Vec<&String>>());
+        if debug::enabled(debug::SCOPES) {
+            for so in self.link_map.iter() {
+                debug!(debug::SCOPES, "object={} local scope: {:?}", so.name, so.scope.iter().map(|&idx| &self.link_map[idx].name).collect::<Vec<&String>>());
+            }
+        }
+
+        // a missing version is fatal, since nothing would bind to what the object was linked against; ldd just reports them all
+        let missing = self.missing_versions(0);
+        if !self.config.trace_loaded_objects && !missing.is_empty() {
+            return Err(missing[0].to_owned())
+        }
+
+        // ldd mode: we're done once everything is loaded, and mustn't run anything
+        if self.config.trace_loaded_objects {
+            for version in &missing {
+                error!("{}", version);
+            }
+            self.trace(&not_found);
+            if self.config.warn {
+                self.report_undefined();
+            }
+            utils::_exit(if not_found.is_empty() && missing.is_empty() { 0 } else { 1 });
+            return Ok (Vec::new())
+        }
 
+        // every module loaded at startup gets a block in the static TLS area, in load order, so the executable's is closest to the thread pointer;
+        // this has to happen before relocation, since the TLS relocations need the module ids and offsets
         for (i, so) in self.link_map.iter().enumerate() {
-            self.relocate_got(i, so);
+            if !self.is_rtld(i) {
+                self.tls.register(i, so.load_bias, &so.phdrs);
+            }
         }
 
+        // debuggers find every object, and their symbols, through the executable's DT_DEBUG; they're told the list is changing now, and that it's consistent once everything is relocated
+        unsafe {
+            r_debug::init(self.base);
+            r_debug::state(r_debug::RT_ADD);
+            let executable = &self.link_map[0];
+            if let Some (phdr) = executable.phdrs.iter().find(|phdr| phdr.p_type == program_header::PT_DYNAMIC) {
+                r_debug::publish((phdr.p_vaddr + executable.load_bias) as *mut dyn::Dyn);
+            }
+            for (i, so) in self.link_map.iter().enumerate() {
+                if !self.is_rtld(i) {
+                    r_debug::add(so.load_bias, if i == 0 { "" } else { &so.path }, so.dynamic.as_ptr());
+                }
+            }
+            let name = self.interpreter().unwrap_or(SONAME);
+            r_debug::add(self.load_bias, name, self.dynamic.as_ptr());
+        }
+
+        self.add_nodes(0);
+
+        // <join>
+        // 2. relocate all
+        // after _all_ SharedObject have been loaded, it is safe to relocate, since we stick to the ELF symbol search rule of first searching the executable, then each of its DT_NEEDED in order, then the deps of the first DT_NEEDED, and if not found, then the deps of the second DT_NEEDED, etc., i.e., breadth-first search.  Why this is allowed to continue past the executable's _OWN_ dependency list is anyone's guess; a penchant for chaos perhaps?
+
+        // IFUNC resolvers essentially execute arbitrary code, including calling into the GOT, e.g., a libm ifunc for `__exp_finite` calls `__get_cpu_features` in libc, so the object defining one has to be completely relocated before anything binds to it;
+        // like ld-so, we relocate in reverse search order, so every object's dependencies are usually done before it is, and the executable, with its copy relocations, comes last
+        unsafe { cpu_features::init(block.getauxval(auxv::AT_HWCAP).unwrap_or(0), block.getauxval(auxv::AT_HWCAP2).unwrap_or(0)); }
+        debug!(debug::RELOC, "cpu features: {:?}", cpu_features::get());
+
         // I believe we can parallelize the relocation pass by:
         // 1. skipping constructors, or blocking until the linkmaps deps are signalled as finished
         // 2. if skip, rerun through the link map again and call each constructor, since the GOT was prepared and now dynamic calls are ready
-        for (i, so) in self.link_map.iter().enumerate() {
-            self.relocate_plt(so, i == 0);
+        for (i, so) in self.link_map.iter().enumerate().rev() {
+            if self.is_rtld(i) {
+                continue
+            }
+            try!(self.relocate_got(i, so));
+            if i == 0 {
+                try!(self.relocate_copy(so));
+            }
+            try!(self.relocate_plt(i, so));
+            self.relocate_irelative(so);
+            try!(self.protect_relro(so));
         }
 
+        r_debug::state(r_debug::RT_CONSISTENT);
+
         // <join>
         // 3. relocate executable and transfer control
 
 //        println!("Relocating executable");
 //        self.relocate_got(0, &self.link_map[0]);
 
-        // we safely loaded and relocated everything, so we can now forget the working_set so it doesn't segfault when we try to access it back again after passing through assembly to `dryad_resolve_symbol`, which from the compiler's perspective means it needs to be dropped
-        println!("<dryad> link_map ptr: {:#?}, cap = len: {}", self.link_map.as_ptr(), self.link_map.capacity() == self.link_map.len());
-        mem::forget(&self.link_map);
-//        mem::forget(self);
+        // the linker was `install`ed before we were called, so the link map outlives us, and `dryad_resolve_symbol` and `dlopen` can keep using it once the program is running
+        debug!(debug::RELOC, "link_map ptr: {:#?}, cap = len: {}", self.link_map.as_ptr(), self.link_map.capacity() == self.link_map.len());
+
+        let objects: Vec<usize> = (0..self.link_map.len()).collect();
+        let order = self.init_order(&objects);
+        debug!(debug::LIBS, "init order: {:?}", order.iter().map(|&idx| &self.link_map[idx].name).collect::<Vec<&String>>());
+        unsafe { FINALIZERS = Box::into_raw(Box::new(self.finalizers(&order))); }
+        // the executable's `DT_PREINIT_ARRAY` comes before everything
+        let mut initializers: Vec<u64> = self.link_map[0].preinit_array().iter().cloned().filter(|preinit| is_valid_function(*preinit)).collect();
+        initializers.extend(self.initializers(&order));
+        self.args = (block.argc, block.argv.as_ptr(), block.env.as_ptr());
+
+        // finally, build the initial thread's TLS and switch %fs over to it; after this we can't use our own thread locals
+        try!(unsafe { self.tls.install(block.getauxval(auxv::AT_RANDOM).unwrap_or(0)) });
+
+        // constructors can use TLS, and call through the PLT, so running them has to be the very last thing `_dryad_init` does, once it's done with us
+        Ok (initializers)
+    }
+
+    /// Returns the link map index of the loaded object whose mapping contains `addr`, e.g., the return address of a call from it
+    fn caller(&self, addr: u64) -> Option<usize> {
+        self.link_map.iter().position(|so| !so.unloaded && so.contains(addr))
+    }
+
+    /// The handle `dlopen` returns for the object at `idx`, i.e., its `LinkNode`
+    pub fn handle(&self, idx: usize) -> *const LinkNode {
+        &*self.nodes[idx] as *const LinkNode
+    }
+
+    /// Returns the link map index of the object `handle` was returned for, or `None` if it isn't a handle, or the object has since been unloaded
+    pub fn handle_index(&self, handle: *const LinkNode) -> Option<usize> {
+        let idx = self.nodes.iter().position(|node| &**node as *const LinkNode == handle);
+        match idx {
+            Some (idx) if !self.link_map[idx].unloaded => Some (idx),
+            _ => None,
+        }
+    }
+
+    /// The loading and linking half of `LinkerGuard::dlopen`; returns the object's link map index, or `None`, and the constructors which have to run before the `dlopen` returns
+    fn dlopen_locked(&mut self, filename: Option<&str>, flags: i32, caller: u64) -> Result<(Option<usize>, Vec<u64>), String> {
+        let filename = match filename {
+            Some (filename) => filename,
+            None => return Ok ((Some (0), Vec::new())),
+        };
+        let first = self.link_map.len();
+        let idx = if flags & dlfcn::RTLD_NOLOAD != 0 {
+            match self.find_loaded(filename) {
+                Some (idx) => idx,
+                None => return Ok ((None, Vec::new())),
+            }
+        } else {
+            let requester = self.caller(caller).unwrap_or(0);
+            match try!(self.load(filename, requester)) {
+                Some (idx) => idx,
+                None => return Err(format!("<dryad> {}: cannot open shared object file: No such file or directory", filename)),
+            }
+        };
+        debug!(debug::FILES, "dlopen {} [{}] with flags {:#x}", self.link_map[idx].path, idx, flags);
+
+        let initializers = if idx >= first {
+            match self.link_objects(idx, first, flags) {
+                Ok (initializers) => initializers,
+                Err (msg) => {
+                    self.discard(first);
+                    return Err(msg)
+                }
+            }
+        } else {
+            Vec::new()
+        };
+
+        {
+            let object = &mut self.link_map[idx];
+            object.refcount += 1;
+            if flags & dlfcn::RTLD_NODELETE != 0 {
+                object.flags_1 |= dyn::DF_1_NODELETE;
+            }
+        }
+        // glibc appends the newly global objects to the end of the global scope, whereas ours stays in load order; the two only differ when an object which was loaded `RTLD_LOCAL` is promoted later on
+        if flags & dlfcn::RTLD_GLOBAL != 0 {
+            for dep in self.link_map[idx].scope.clone() {
+                self.link_map[dep].global = true;
+            }
+        }
+
+        Ok ((Some (idx), initializers))
+    }
+
+    /// Links the objects a `dlopen` of the object at `idx` has just loaded, i.e., everything from `first` on, the same way `link` does at startup: loads their dependencies, registers their TLS modules, tells the debugger about them, and relocates them.
+    /// Returns their constructors, in the order they should run; on error, the caller has to `discard` them
+    fn link_objects(&mut self, idx: usize, first: usize, flags: i32) -> Result<Vec<u64>, String> {
+        if self.link_map[idx].flags_1 & dyn::DF_1_NOOPEN != 0 {
+            return Err(format!("<dryad> {}: shared object cannot be dlopen()ed", self.link_map[idx].path))
+        }
+        try!(self.load_dependencies(first, &mut Vec::new()));
+        let missing = self.missing_versions(first);
+        if !missing.is_empty() {
+            return Err(missing[0].to_owned())
+        }
+        for i in first..self.link_map.len() {
+            let scope = self.local_scope(i);
+            let so = &mut self.link_map[i];
+            so.scope = scope;
+            if flags & dlfcn::RTLD_NOW != 0 {
+                so.flags_1 |= dyn::DF_1_NOW;
+            }
+        }
+        debug!(debug::SCOPES, "object={} local scope: {:?}", self.link_map[idx].name, self.link_map[idx].scope.iter().map(|&idx| &self.link_map[idx].name).collect::<Vec<&String>>());
+
+        // unlike at startup, an undefined symbol is an error, since the program can't have been linked against it
+        for i in first..self.link_map.len() {
+            if let Some (&sym) = self.undefined(i).first() {
+                let so = &self.link_map[i];
+                let name = &so.strtab[so.symtab[sym].st_name as usize];
+                return match so.versions.version(sym) {
+                    Some (version) => Err(format!("<dryad> {}: undefined symbol: {}, version {}", so.path, name, version.name)),
+                    None => Err(format!("<dryad> {}: undefined symbol: {}", so.path, name)),
+                }
+            }
+        }
 
+        // the static TLS area is long gone, so these modules only ever live in the DTV
+        for i in first..self.link_map.len() {
+            let so = &self.link_map[i];
+            self.tls.register_dynamic(i, so.load_bias, &so.phdrs);
+        }
+        self.add_nodes(first);
+
+        unsafe {
+            r_debug::state(r_debug::RT_ADD);
+            for so in &self.link_map[first..] {
+                r_debug::add(so.load_bias, &so.path, so.dynamic.as_ptr());
+            }
+        }
+        for (i, so) in self.link_map.iter().enumerate().skip(first).rev() {
+            try!(self.relocate_got(i, so));
+            try!(self.relocate_plt(i, so));
+            self.relocate_irelative(so);
+            try!(self.protect_relro(so));
+        }
+        r_debug::state(r_debug::RT_CONSISTENT);
+
+        // every new object is referenced by each of its dependents, so it's only unloaded once they are, and every object one of them bound to by each of those too; the lazily bound ones are added as they're bound
+        for i in first..self.link_map.len() {
+            for dep in self.link_map[i].needed.clone() {
+                self.link_map[dep].refcount += 1;
+            }
+            for (sym, class) in self.symbol_references(i) {
+                if let Some ((definer, _)) = resolve::resolve(&self.link_map, i, sym, class) {
+                    add_reldep(&mut self.link_map, i, definer);
+                }
+            }
+        }
+
+        let objects: Vec<usize> = (first..self.link_map.len()).collect();
+        let order = self.init_order(&objects);
+        debug!(debug::LIBS, "init order: {:?}", order.iter().map(|&idx| &self.link_map[idx].name).collect::<Vec<&String>>());
+        Ok (self.initializers(&order))
+    }
+
+    /// Undoes a `dlopen` which failed part of the way through, by unmapping every object from `first` on, and forgetting everything about them; nothing else references them yet
+    fn discard(&mut self, first: usize) {
+        let objects: Vec<usize> = (first..self.link_map.len()).collect();
+        r_debug::state(r_debug::RT_DELETE);
+        while self.link_map.len() > first {
+            let so = self.link_map.pop().unwrap();
+            debug!(debug::FILES, "discarding {}", so.path);
+            unsafe {
+                r_debug::remove(so.dynamic.as_ptr());
+                mmap::munmap(so.map_begin as *const u64, (so.map_end - so.map_begin) as usize);
+            }
+        }
+        self.nodes.truncate(first);
+        self.tls.unregister(&objects);
+        resolve::forget_unique(first);
+        r_debug::state(r_debug::RT_CONSISTENT);
+    }
+
+    /// Returns the address of the definition of `name` which `handle` finds, for the object containing `caller`; the default version of a versioned symbol is the one that's found.
+    /// An IFUNC's address is whatever its resolver picks, and a thread local's is the calling thread's instance of it
+    pub fn dlsym(&self, handle: dlfcn::Handle, name: &str, caller: u64) -> Result<u64, String> {
+        let link_map: &[SharedObject] = &self.link_map;
+        let found = match handle {
+            dlfcn::Handle::Object(0) => resolve::lookup(link_map, name, None, resolve::Class::Address),
+            dlfcn::Handle::Object(idx) => resolve::lookup_in(link_map, link_map[idx].scope.iter().cloned(), name, None, resolve::Class::Address),
+            // what a reference from the caller would bind to
+            dlfcn::Handle::Default => match resolve::lookup(link_map, name, None, resolve::Class::Address) {
+                None => match self.caller(caller) {
+                    Some (idx) if !link_map[idx].global => resolve::lookup_in(link_map, link_map[idx].scope.iter().cloned(), name, None, resolve::Class::Address),
+                    _ => None,
+                },
+                found => found,
+            },
+            // whatever the caller's own definition interposes on, i.e., the next definition in whichever scope the caller was found in
+            dlfcn::Handle::Next => match self.caller(caller) {
+                Some (idx) if link_map[idx].global => resolve::lookup_in(link_map, resolve::global_scope(link_map).filter(|&i| i > idx), name, None, resolve::Class::Address),
+                Some (idx) => resolve::lookup_in(link_map, link_map[idx].scope.iter().cloned().skip(1), name, None, resolve::Class::Address),
+                None => return Err(format!("<dryad> RTLD_NEXT used in code not dynamically loaded")),
+            },
+        };
+        let (definer, definition) = match found {
+            Some (found) => found,
+            None => {
+                return match handle {
+                    dlfcn::Handle::Object(idx) if idx != 0 => Err(format!("<dryad> {}: undefined symbol: {}", link_map[idx].path, name)),
+                    _ => Err(format!("<dryad> undefined symbol: {}", name)),
+                }
+            }
+        };
+        debug!(debug::BINDINGS, "dlsym binding {} to {} [{}]", name, link_map[definer].name, definer);
+        let address = definition.st_value + link_map[definer].load_bias;
+        match sym::st_type(definition.st_info) {
+            sym::STT_GNU_IFUNC => Ok (resolve::ifunc(address)),
+            sym::STT_TLS => match self.tls.module(definer) {
+                Some (module) => {
+                    let ti = tls::TlsIndex { module: module.id as u64, offset: definition.st_value };
+                    Ok (unsafe { tls::__tls_get_addr(&ti) } as u64)
+                },
+                None => Err(format!("<dryad> {}: no TLS module for {}", link_map[definer].path, name)),
+            },
+            _ => Ok (address),
+        }
+    }
+
+    /// Drops a reference to the object at `idx`, and if that was the last one, to each of its dependencies and the objects it bound to too; every object nothing references anymore is appended to `dead`, dependents before their dependencies.
+    /// Objects loaded at startup, or `dlopen`ed `RTLD_NODELETE`, or linked `-z nodelete`, or whose definition of a unique symbol something bound to, are never released
+    fn release(&mut self, idx: usize, dead: &mut Vec<usize>) -> Result<(), String> {
+        if resolve::defines_unique(idx) {
+            self.link_map[idx].flags_1 |= dyn::DF_1_NODELETE;
+        }
+        if self.link_map[idx].flags_1 & dyn::DF_1_NODELETE != 0 {
+            return Ok (())
+        }
+        if self.link_map[idx].refcount == 0 {
+            return Err(format!("<dryad> {}: shared object is not open", self.link_map[idx].path))
+        }
+        self.link_map[idx].refcount -= 1;
+        if self.link_map[idx].refcount == 0 {
+            dead.push(idx);
+            let deps: Vec<usize> = {
+                let so = &self.link_map[idx];
+                so.needed.iter().chain(so.reldeps.iter()).cloned().collect()
+            };
+            for dep in deps {
+                try!(self.release(dep, dead));
+            }
+        }
         Ok (())
     }
+
+    /// Unmaps the objects `release` found `dead`, once their finalizers have run; an unloaded object keeps its place in the link map, so every other object's indices stay valid, but it's never found, or searched, again
+    fn unload(&mut self, dead: &[usize]) {
+        r_debug::state(r_debug::RT_DELETE);
+        for &idx in dead {
+            let so = &mut self.link_map[idx];
+            debug!(debug::FILES, "unloading {}", so.path);
+            unsafe {
+                r_debug::remove(so.dynamic.as_ptr());
+                mmap::munmap(so.map_begin as *const u64, (so.map_end - so.map_begin) as usize);
+            }
+            so.unloaded = true;
+            so.global = false;
+        }
+        self.tls.unregister(dead);
+        r_debug::state(r_debug::RT_CONSISTENT);
+    }
+
+    /// Returns the finalizers of every object `dlopen` loaded that's still loaded, in the order they should run when the program exits, which is before any of the objects loaded at startup
+    pub fn dlopen_finalizers(&self) -> Vec<u64> {
+        let open: Vec<usize> = (self.startup..self.link_map.len()).filter(|&idx| !self.link_map[idx].unloaded).collect();
+        let order = self.init_order(&open);
+        self.finalizers(&order)
+    }
+}
+
+impl LinkerGuard {
+    /// Loads `filename`, which can be a path or a soname, on behalf of the object containing `caller`, whose `DT_RUNPATH` and `$ORIGIN` are searched, and links it and its dependencies exactly like the objects loaded at startup were;
+    /// returns its link map index, or `None` if `flags` has `RTLD_NOLOAD` and it isn't already loaded. A `filename` of `None`, i.e., `dlopen(NULL)`, returns the executable's index, 0, whose handle searches the global scope.
+    /// Its constructors, and those of any dependencies it loaded, have run by the time this returns; they run with the link map still locked, so they can call `dlopen` themselves, but any other thread has to wait
+    pub fn dlopen(&mut self, filename: Option<&str>, flags: i32, caller: u64) -> Result<Option<usize>, String> {
+        let (idx, initializers) = try!(self.dlopen_locked(filename, flags, caller));
+        run_init(&initializers);
+        Ok (idx)
+    }
+
+    /// Drops the reference a `dlopen` of the object at `idx` took; if nothing references it anymore, it's finalized and unmapped, along with every dependency nothing else references.
+    /// Like constructors, the finalizers run with the link map still locked, but nothing borrowing the linker
+    pub fn dlclose(&mut self, idx: usize) -> Result<(), String> {
+        let mut dead = Vec::new();
+        let result = self.release(idx, &mut dead);
+        if !dead.is_empty() {
+            let finalizers = {
+                let order = self.init_order(&dead);
+                self.finalizers(&order)
+            };
+            for fini in finalizers {
+                unsafe { mem::transmute::<u64, extern fn()>(fini)(); }
+            }
+            self.unload(&dead);
+        }
+        result
+    }
 }
@@ -0,0 +1,160 @@
+/// The debugger interface, as glibc's `<link.h>` defines it: gdb and lldb find the loaded objects through the `struct r_debug` the executable's `DT_DEBUG` entry points at,
+/// and set a breakpoint on its `r_brk`, i.e., `_dl_debug_state`, which we call whenever the list of objects is about to change, and once it's consistent again:
+///
+/// ```text
+/// _r_debug { r_version: 1, r_map, r_brk: &_dl_debug_state, r_state, r_ldbase }
+///               |
+///               v
+///            link_map { l_addr, l_name: "", l_ld } <-> { .., "/lib/libc.so.6", .. } <-> ... <-> { .., "/tmp/dryad.so.1", .. }
+/// ```
+///
+/// `l_addr` is the object's load bias, `l_name` its path, or "" for the executable, and `l_ld` its `_DYNAMIC`; the debugger reads the symbols of each from there.
+/// N.B.: the debugger only reads these, and only while the program is stopped, so they're never locked
+
+use std::ffi::CString;
+use std::ptr;
+
+use binary::elf::dyn;
+use binary::elf::dyn::Dyn;
+
+/// The list is consistent, and safe to read
+pub const RT_CONSISTENT: i32 = 0;
+/// An object is about to be added
+pub const RT_ADD: i32 = 1;
+/// An object is about to be removed
+pub const RT_DELETE: i32 = 2;
+
+/// The first five fields of glibc's `struct link_map`; everything after them is private to the dynamic linker, and debuggers don't read it
+#[repr(C)]
+pub struct LinkMap {
+    pub l_addr: u64,
+    pub l_name: *const u8,
+    pub l_ld: *const Dyn,
+    pub l_next: *mut LinkMap,
+    pub l_prev: *mut LinkMap,
+}
+
+#[repr(C)]
+pub struct RDebug {
+    pub r_version: i32,
+    pub r_map: *mut LinkMap,
+    pub r_brk: u64,
+    pub r_state: i32,
+    pub r_ldbase: u64,
+}
+
+/// Debuggers look this up by name in the dynamic linker when the executable's `DT_DEBUG` isn't set yet, e.g., when attaching to it before we've run
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static mut _r_debug: RDebug = RDebug {
+    r_version: 0,
+    r_map: 0 as *mut LinkMap,
+    r_brk: 0,
+    r_state: RT_CONSISTENT,
+    r_ldbase: 0,
+};
+
+/// The debugger's breakpoint; it reads `_r_debug.r_state` when it stops here
+#[no_mangle]
+#[inline(never)]
+pub extern fn _dl_debug_state() {
+    // keeps this from being folded away, or merged with some other empty function, since the debugger needs an address of its own to break on
+    unsafe {
+        asm!("" : : : "memory" : "volatile");
+    }
+}
+
+/// Initializes `_r_debug` for the dynamic linker loaded at `ldbase`
+pub unsafe fn init(ldbase: u64) {
+    _r_debug.r_version = 1;
+    _r_debug.r_brk = _dl_debug_state as u64;
+    _r_debug.r_ldbase = ldbase;
+}
+
+/// Points the `DT_DEBUG` entry of the executable's `_DYNAMIC`, which ends at its `DT_NULL`, at `_r_debug`, if it has one; must be done before its RELRO segment is protected
+pub unsafe fn publish(mut dynamic: *mut Dyn) {
+    while (*dynamic).d_tag != dyn::DT_NULL {
+        if (*dynamic).d_tag == dyn::DT_DEBUG {
+            (*dynamic).d_val = &_r_debug as *const RDebug as u64;
+        }
+        dynamic = dynamic.offset(1);
+    }
+}
+
+/// Tells the debugger the list is about to change, i.e., `RT_ADD` or `RT_DELETE`, or is consistent again
+pub fn state(state: i32) {
+    unsafe { _r_debug.r_state = state; }
+    _dl_debug_state();
+}
+
+/// Appends the object loaded at `l_addr`, with its `_DYNAMIC` at `l_ld`, to the list; should be called between `state(RT_ADD)` and `state(RT_CONSISTENT)`
+pub unsafe fn add(l_addr: u64, name: &str, l_ld: *const Dyn) -> *mut LinkMap {
+    let l_name = match CString::new(name) {
+        Ok (name) => name.into_raw() as *const u8,
+        Err (_) => ptr::null(),
+    };
+    let map = Box::into_raw(Box::new(LinkMap {
+        l_addr: l_addr,
+        l_name: l_name,
+        l_ld: l_ld,
+        l_next: ptr::null_mut(),
+        l_prev: ptr::null_mut(),
+    }));
+    if _r_debug.r_map.is_null() {
+        _r_debug.r_map = map;
+    } else {
+        let mut last = _r_debug.r_map;
+        while !(*last).l_next.is_null() {
+            last = (*last).l_next;
+        }
+        (*last).l_next = map;
+        (*map).l_prev = last;
+    }
+    map
+}
+
+/// Unlinks the object whose `_DYNAMIC` is at `l_ld` from the list, and frees its entry; should be called between `state(RT_DELETE)` and `state(RT_CONSISTENT)`
+pub unsafe fn remove(l_ld: *const Dyn) {
+    let mut map = _r_debug.r_map;
+    while !map.is_null() && (*map).l_ld != l_ld {
+        map = (*map).l_next;
+    }
+    if map.is_null() {
+        return
+    }
+    if (*map).l_prev.is_null() {
+        _r_debug.r_map = (*map).l_next;
+    } else {
+        (*(*map).l_prev).l_next = (*map).l_next;
+    }
+    if !(*map).l_next.is_null() {
+        (*(*map).l_next).l_prev = (*map).l_prev;
+    }
+    let map = Box::from_raw(map);
+    if !map.l_name.is_null() {
+        drop(CString::from_raw(map.l_name as *mut _));
+    }
+}
+
+#[test]
+fn link_map_t() {
+    let mut dynamic = [Dyn { d_tag: dyn::DT_DEBUG, d_val: 0 }, Dyn { d_tag: dyn::DT_NULL, d_val: 0 }];
+    unsafe {
+        publish(dynamic.as_mut_ptr());
+        assert_eq!(dynamic[0].d_val, &_r_debug as *const RDebug as u64);
+        let ld = |i: usize| (0x1000 * i) as *const Dyn;
+        add(0, "", ld(1));
+        add(0x7f00_0000, "/lib/libc.so.6", ld(2));
+        let last = add(0x7f10_0000, "/tmp/dryad.so.1", ld(3));
+        remove(ld(2));
+        let first = _r_debug.r_map;
+        assert_eq!((*first).l_next, last);
+        assert_eq!((*last).l_prev, first);
+        assert_eq!(*(*last).l_name.offset(15), 0);
+        remove(ld(1));
+        assert_eq!(_r_debug.r_map, last);
+        assert!((*last).l_prev.is_null());
+        remove(ld(3));
+        assert!(_r_debug.r_map.is_null());
+    }
+}
@@ -0,0 +1,199 @@
+/// Symbol resolution: binding a reference in one object to a definition in the link map, following the ELF rules rather than just taking the first symbol with the right name:
+///
+/// 1. a reference to a local symbol, or to one the object itself defines with hidden, internal or protected visibility, is bound to the object's own definition, and never preempted
+/// 2. otherwise the global scope is searched in order, then, if the object isn't in it, i.e., it was `dlopen`ed `RTLD_LOCAL`, its own local scope; only exported definitions count, i.e., defined (or a canonical PLT entry, see 7), non-local, with default or protected visibility (see `sym::is_exported`)
+/// 3. the first strong (`STB_GLOBAL` or `STB_GNU_UNIQUE`) definition wins; a weak definition is only used if there's no strong one anywhere in the scope
+/// 4. an `STB_GNU_UNIQUE` symbol has exactly one definition in the whole process, which is whichever was bound first, no matter how many objects define it, e.g., C++ inline statics and template static members
+/// 5. an undefined weak reference which nothing defines is bound to 0, e.g., `__gmon_start__`, which the program checks before calling
+/// 6. a reference bound to an `STT_GNU_IFUNC` definition gets whatever its resolver returns, rather than the resolver itself
+/// 7. a non-PIE executable's canonical PLT entry for a function, i.e., an undefined symbol with a value, is the definition of every reference to the function except for the calls through a PLT, so the function's address is the same everywhere (see `Class`)
+
+use std::mem;
+
+use binary::elf::rela;
+use binary::elf::sym;
+use binary::elf::sym::Sym;
+use binary::elf::gnu_hash;
+use binary::elf::image::SharedObject;
+use binary::elf::version::Version;
+use arch::x86::cpu_features;
+use arch::x86::cpu_features::CpuFeatures;
+use utils::debug;
+use linker;
+
+/// How dryad calls IFUNC resolvers: with `AT_HWCAP`, `AT_HWCAP2` and the CPU's features; glibc's x86-64 resolvers take no arguments, and just ignore them
+type IfuncResolver = extern "C" fn(u64, u64, *const CpuFeatures) -> u64;
+
+/// The class of the relocation a symbol is being bound for, like glibc's `ELF_RTYPE_CLASS_*`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Class {
+    /// `R_X86_64_JUMP_SLOT`, i.e., a call through the object's own PLT, which has to reach the function itself; bound to the executable's canonical PLT entry, it would just call back through the executable's own PLT slot
+    Plt,
+    /// Everything else, which takes the symbol's address, e.g., `R_X86_64_GLOB_DAT` and `R_X86_64_64`, or `dlsym`; these have to agree with the executable about a function's address, so its canonical PLT entry is the definition
+    Address,
+}
+
+impl Class {
+    /// The class of the relocation type `typ`
+    pub fn of(typ: u64) -> Class {
+        if typ == rela::R_X86_64_JUMP_SLOT { Class::Plt } else { Class::Address }
+    }
+}
+
+/// Every `STB_GNU_UNIQUE` symbol bound so far: its name, the link map index of the object whose definition won, and that definition.
+/// There are only ever a handful of these, so a linear search is fine, and unlike a `HashMap` it doesn't need our thread locals, which are gone once the program's TLS is installed.
+/// Any thread can be binding, so it's only touched with the link map locked; lookups normally hold the lock already, but it's reentrant, and this doesn't rely on it
+static mut UNIQUE: *mut Vec<(String, usize, *const Sym)> = 0 as *mut Vec<(String, usize, *const Sym)>;
+
+/// Returns the process-wide definition of the unique symbol `name`, registering the definition `(idx, definition)` if this is the first time it's been bound
+fn unique<'process> (name: &str, idx: usize, definition: &'process Sym) -> (usize, &'process Sym) {
+    linker::lock_link_map();
+    let unique = unsafe {
+        if UNIQUE.is_null() {
+            UNIQUE = Box::into_raw(Box::new(Vec::new()));
+        }
+        let table = &mut *UNIQUE;
+        match table.iter().position(|&(ref unique, _, _)| unique == name) {
+            Some (i) => (table[i].1, &*table[i].2),
+            None => {
+                table.push((name.to_string(), idx, definition as *const Sym));
+                (idx, definition)
+            }
+        }
+    };
+    linker::unlock_link_map();
+    unique
+}
+
+/// Whether the definition of some unique symbol that's been bound is the one in the object at `idx`; that object can never be unloaded, like with glibc, since other objects bound to it, and the definition has to stay the same for the life of the process
+pub fn defines_unique(idx: usize) -> bool {
+    linker::lock_link_map();
+    let defines = unsafe { !UNIQUE.is_null() && (*UNIQUE).iter().any(|&(_, unique_idx, _)| unique_idx == idx) };
+    linker::unlock_link_map();
+    defines
+}
+
+/// Forgets the unique definitions of the objects at link map indices `first` and after, e.g., because a failed `dlopen` is unmapping them
+pub fn forget_unique(first: usize) {
+    linker::lock_link_map();
+    unsafe {
+        if !UNIQUE.is_null() {
+            (*UNIQUE).retain(|&(_, unique_idx, _)| unique_idx < first);
+        }
+    }
+    linker::unlock_link_map();
+}
+
+/// Searches the objects at the link map indices `scope`, in order, for the definition of `name` in `version` (or the default version, if `None`) which can satisfy a relocation of `class`, and returns the link map index of the object defining it along with the definition;
+/// the gnu hash is computed once up front, and each object's bloom filter then rejects most of the objects which don't define it without touching their symbol tables
+pub fn lookup_in<'process, I: Iterator<Item=usize>> (link_map: &[SharedObject<'process>], scope: I, name: &str, version: Option<&Version>, class: Class) -> Option<(usize, &'process Sym)> {
+    let hash = gnu_hash::hash(name);
+    let mut weak = None;
+    for i in scope {
+        let so = &link_map[i];
+        if so.unloaded {
+            continue
+        }
+        if let Some(definition) = so.find_sym(name, hash, version) {
+            // the executable's canonical PLT entry; the function itself is further on
+            if class == Class::Plt && sym::is_import(definition) {
+                continue
+            }
+            match sym::st_bind(definition.st_info) {
+                sym::STB_WEAK => {
+                    if weak.is_none() {
+                        weak = Some ((i, definition));
+                    }
+                },
+                sym::STB_GNU_UNIQUE => return Some (unique(name, i, definition)),
+                _ => return Some ((i, definition)),
+            }
+        }
+    }
+    weak
+}
+
+/// The link map indices of the global scope, in order
+pub fn global_scope<'a> (link_map: &'a [SharedObject]) -> Box<Iterator<Item=usize> + 'a> {
+    Box::new((0..link_map.len()).filter(move |&i| link_map[i].global))
+}
+
+/// Searches the global scope for the definition of `name` in `version`; see `lookup_in`
+pub fn lookup<'process> (link_map: &[SharedObject<'process>], name: &str, version: Option<&Version>, class: Class) -> Option<(usize, &'process Sym)> {
+    lookup_in(link_map, global_scope(link_map), name, version, class)
+}
+
+/// Binds the symbol at index `sym` in the symbol table of the object at `idx`, e.g., the symbol of one of its relocations, of `class`, and returns the link map index of the object defining it along with the definition;
+/// returns `None` if nothing defines it, which is only an error if the reference isn't weak
+pub fn resolve<'process> (link_map: &[SharedObject<'process>], idx: usize, sym: usize, class: Class) -> Option<(usize, &'process Sym)> {
+    let object = &link_map[idx];
+    let symbol = &object.symtab[sym];
+    if !sym::is_import(symbol) && (sym::st_bind(symbol.st_info) == sym::STB_LOCAL || sym::st_visibility(symbol.st_other) != sym::STV_DEFAULT) {
+        return Some ((idx, symbol))
+    }
+    let name = &object.strtab[symbol.st_name as usize];
+    let version = object.versions.version(sym);
+    let found = match lookup(link_map, name, version, class) {
+        None if !object.global => lookup_in(link_map, object.scope.iter().cloned(), name, version, class),
+        found => found,
+    };
+    match found {
+        Some ((definer, definition)) => {
+            debug!(debug::BINDINGS, "binding file {} [{}] to {} [{}]: {} symbol `{}'", object.name, idx, link_map[definer].name, definer, sym::bind_to_str(sym::st_bind(definition.st_info)), name);
+            Some ((definer, definition))
+        },
+        None => None,
+    }
+}
+
+/// Calls the IFUNC resolver at `resolver`, and returns the implementation it picked; the object defining it must already be relocated, see `cpu_features::init`
+pub fn ifunc (resolver: u64) -> u64 {
+    let features = cpu_features::get();
+    let address = unsafe { mem::transmute::<u64, IfuncResolver>(resolver)(features.hwcap, features.hwcap2, features) };
+    debug!(debug::RELOC, "ifunc at {:#x} resolved to {:#x}", resolver, address);
+    address
+}
+
+/// Returns the link map index of the object defining the symbol at index `sym` in the object at `idx`, along with the address and size of the definition it's bound to, i.e., `S` and `Z` in the relocation formulas;
+/// both are 0 for an undefined weak reference, which is "defined" by `idx` itself, and `None` means the symbol is undefined and the reference strong
+pub fn resolve_definition (link_map: &[SharedObject], idx: usize, sym: usize, class: Class) -> Option<(usize, u64, u64)> {
+    match resolve(link_map, idx, sym, class) {
+        Some ((definer, definition)) => {
+            let value = definition.st_value + link_map[definer].load_bias;
+            if sym::st_type(definition.st_info) == sym::STT_GNU_IFUNC {
+                Some ((definer, ifunc(value), definition.st_size))
+            } else {
+                Some ((definer, value, definition.st_size))
+            }
+        },
+        None if is_weak_reference(&link_map[idx].symtab[sym]) => Some ((idx, 0, 0)),
+        None => None,
+    }
+}
+
+/// Returns the link map index of the object defining the symbol at index `sym` in the object at `idx`, and the address it's bound to; see `resolve_definition`
+pub fn resolve_address (link_map: &[SharedObject], idx: usize, sym: usize, class: Class) -> Option<(usize, u64)> {
+    resolve_definition(link_map, idx, sym, class).map(|(definer, value, _)| (definer, value))
+}
+
+/// Whether an undefined reference is allowed to stay undefined
+#[inline]
+pub fn is_weak_reference(symbol: &Sym) -> bool {
+    sym::st_bind(symbol.st_info) == sym::STB_WEAK
+}
+
+#[test]
+fn unique_t() {
+    let first = Sym { st_name: 0, st_info: sym::STB_GNU_UNIQUE << 4 | sym::STT_OBJECT, st_other: 0, st_shndx: 1, st_value: 0x1000, st_size: 8 };
+    let second = Sym { st_name: 0, st_info: sym::STB_GNU_UNIQUE << 4 | sym::STT_OBJECT, st_other: 0, st_shndx: 1, st_value: 0x2000, st_size: 8 };
+    assert_eq!(unique("_ZZN3foo3barEvE1x", 3, &first).0, 3);
+    let (idx, definition) = unique("_ZZN3foo3barEvE1x", 5, &second);
+    assert_eq!(idx, 3);
+    assert_eq!(definition as *const Sym, &first as *const Sym);
+    assert_eq!(unique("_ZZN3foo3bazEvE1y", 5, &second).0, 5);
+    assert!(defines_unique(5));
+    forget_unique(4);
+    assert!(!defines_unique(5));
+    assert!(defines_unique(3));
+    assert_eq!(unique("_ZZN3foo3bazEvE1y", 6, &first).0, 6);
+}
@@ -0,0 +1,97 @@
+/// Library search paths: splitting `LD_LIBRARY_PATH`, `DT_RPATH` and `DT_RUNPATH` strings into directories, and expanding the dynamic string tokens in them.
+/// The order a `DT_NEEDED` entry is searched for is (see `man ld.so`):
+///
+/// 1. if it contains a slash, it's a path, and is opened as is
+/// 2. the `DT_RPATH` of the object which needs it, then of the object which loaded that one, and so on up to the executable, skipping any of them which has a `DT_RUNPATH`; none of them are searched if the object which needs it has a `DT_RUNPATH`
+/// 3. `LD_LIBRARY_PATH`, unless we're running `AT_SECURE`
+/// 4. the `DT_RUNPATH` of the object which needs it, and _only_ that object
+/// 5. the `ld.so.cache`
+/// 6. the default directories
+///
+/// The tokens are `$ORIGIN`, the directory containing the object whose path is being expanded, `$LIB`, the system's library directory name, and `$PLATFORM`, the kernel's `AT_PLATFORM` string;
+/// each can also be written with braces, e.g., `${ORIGIN}`.
+
+/// The trusted system directories searched last, in order
+pub const DEFAULT_DIRS: [&'static str; 4] = ["/lib64", "/usr/lib64", "/lib", "/usr/lib"];
+
+/// What `$LIB` expands to on x86-64
+pub const LIB: &'static str = "lib64";
+
+/// What `$PLATFORM` expands to when the kernel doesn't tell us
+pub const DEFAULT_PLATFORM: &'static str = "x86_64";
+
+/// Returns the directory containing `path`, which is what `$ORIGIN` means for the object at `path`
+pub fn origin(path: &str) -> &str {
+    match path.rfind('/') {
+        Some (0) => "/",
+        Some (idx) => &path[..idx],
+        None => ".",
+    }
+}
+
+/// If `path` starts with `token` at `idx`, either bare or in braces, returns the length of the token as written
+fn token_len(path: &str, idx: usize, token: &str) -> Option<usize> {
+    let rest = &path[idx..];
+    if rest.starts_with("{") && rest[1..].starts_with(token) && rest[1 + token.len()..].starts_with("}") {
+        Some (token.len() + 2)
+    } else if rest.starts_with(token) {
+        // a bare token has to end at something that can't be part of a name, otherwise e.g., `$LIBDIR` would be expanded
+        match rest[token.len()..].chars().next() {
+            Some (c) if c.is_alphanumeric() || c == '_' => None,
+            _ => Some (token.len()),
+        }
+    } else {
+        None
+    }
+}
+
+/// Expands the `$ORIGIN`, `$LIB` and `$PLATFORM` tokens in the single directory `path`, where `origin` is the directory of the object it came from.
+/// Returns `None` if `path` should be ignored: when `secure`, i.e., `AT_SECURE` is set, a path using `$ORIGIN`, or which isn't absolute, could be controlled by the user, so it's dropped; unknown tokens are dropped too
+pub fn expand(path: &str, origin: &str, platform: &str, secure: bool) -> Option<String> {
+    let mut expanded = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(idx) = rest.find('$') {
+        expanded.push_str(&rest[..idx]);
+        let after = idx + 1;
+        if let Some(len) = token_len(rest, after, "ORIGIN") {
+            if secure {
+                return None
+            }
+            expanded.push_str(origin);
+            rest = &rest[after + len..];
+        } else if let Some(len) = token_len(rest, after, "LIB") {
+            expanded.push_str(LIB);
+            rest = &rest[after + len..];
+        } else if let Some(len) = token_len(rest, after, "PLATFORM") {
+            expanded.push_str(platform);
+            rest = &rest[after + len..];
+        } else {
+            return None
+        }
+    }
+    expanded.push_str(rest);
+    if secure && !expanded.starts_with("/") {
+        None
+    } else {
+        Some (expanded)
+    }
+}
+
+/// Splits the search path `paths` (e.g., `LD_LIBRARY_PATH`, or a `DT_RUNPATH`) into its directories, expanding each one, and skipping the empty and ignored ones
+pub fn split(paths: &str, origin: &str, platform: &str, secure: bool) -> Vec<String> {
+    paths.split(|c| c == ':' || c == ';')
+        .filter(|dir| *dir != "")
+        .filter_map(|dir| expand(dir, origin, platform, secure))
+        .collect()
+}
+
+#[test]
+fn expand_t() {
+    assert_eq!(expand("$ORIGIN/../lib", "/opt/app/bin", "x86_64", false), Some ("/opt/app/bin/../lib".to_string()));
+    assert_eq!(expand("${ORIGIN}/$LIB/$PLATFORM", "/opt", "haswell", false), Some ("/opt/lib64/haswell".to_string()));
+    assert_eq!(expand("/usr/$LIB", "/opt", "x86_64", true), Some ("/usr/lib64".to_string()));
+    assert_eq!(expand("$ORIGIN/lib", "/opt", "x86_64", true), None);
+    assert_eq!(expand("lib", "/opt", "x86_64", true), None);
+    assert_eq!(expand("/opt/$LIBDIR", "/opt", "x86_64", false), None);
+    assert_eq!(split("/a::$ORIGIN/b;/c", "/o", "x86_64", false), vec!["/a", "/o/b", "/c"]);
+}
@@ -0,0 +1,400 @@
+/// Thread local storage for the objects dryad loads.
+///
+/// x86-64 uses TLS "variant II": the TLS blocks of every module loaded at startup are laid out at fixed, negative offsets from the thread pointer (`%fs`), i.e., the static TLS area,
+/// with the executable's block closest to it, and the thread control block (TCB) sitting _at_ the thread pointer:
+///
+/// ```text
+///  | module n | ... | module 2 | module 1 | TCB ...
+///  ^ tp - offset_n             ^ tp - offset_1
+///                                         ^ tp == %fs:0
+/// ```
+///
+/// Modules which aren't in the static TLS area, i.e., loaded after startup, are only reachable through `__tls_get_addr` and the thread's dynamic thread vector (DTV),
+/// which maps module ids to the address of that thread's block for the module; those blocks are allocated lazily, the first time the thread touches them, and freed once the module is unloaded, the next time the thread updates its DTV.
+///
+/// See Ulrich Drepper's "ELF Handling For Thread-Local Storage": https://www.akkadia.org/drepper/tls.pdf
+/// N.B.: dryad's _own_ TLS is musl's business, and initialized by `__init_tls` in `Linker::new`; once `install` points `%fs` at the program's TCB, dryad must no longer touch thread locals.
+
+use std::cmp;
+use std::ptr;
+use std::cell::UnsafeCell;
+use std::os::raw::{c_int};
+use std::sync::atomic::{AtomicUsize, AtomicBool, Ordering, ATOMIC_USIZE_INIT, ATOMIC_BOOL_INIT};
+
+use binary::elf::program_header;
+use binary::elf::program_header::ProgramHeader;
+use utils::mmap;
+use utils::page;
+use utils;
+
+/// `arch_prctl` code which sets the `%fs` base
+const ARCH_SET_FS: u64 = 0x1002;
+
+/// How much room we leave at and above the thread pointer for the TCB; libc owns everything past the header, which for glibc is the rest of its `struct pthread`, so we're generous
+const TCB_SIZE: usize = 0x1000;
+
+/// Offsets into the TCB header, as laid out by glibc's x86-64 `tcbhead_t` (musl's `struct pthread` agrees on the first two and the canary)
+const TCB_SELF: isize = 0;
+const TCB_DTV: isize = 1;
+const TCB_SELF_AGAIN: isize = 2;
+const TCB_STACK_GUARD: isize = 5;
+const TCB_POINTER_GUARD: isize = 6;
+
+/// How many extra module slots a DTV gets whenever it's (re)allocated, so that loading a module doesn't always resize every thread's DTV
+const DTV_SLACK: usize = 16;
+
+/// Bumped every time a module is added to or removed from the registry; a DTV whose generation is older might be missing slots, or still have blocks for modules which are gone
+static GENERATION: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Guards the registry and DTV resizing in the `__tls_get_addr` slow path
+static LOCK: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// The registry `__tls_get_addr` consults, which is the linker's; set by `Tls::install`
+static mut REGISTRY: *const Tls = 0 as *const Tls;
+
+#[inline(always)]
+fn round_up(x: usize, align: usize) -> usize {
+    if align <= 1 { x } else { (x + align - 1) & !(align - 1) }
+}
+
+#[inline(always)]
+fn lock() {
+    while LOCK.compare_and_swap(false, true, Ordering::Acquire) {}
+}
+
+#[inline(always)]
+fn unlock() {
+    LOCK.store(false, Ordering::Release);
+}
+
+/// Returns the current thread pointer, which the TCB stores at `%fs:0`
+#[inline(always)]
+pub unsafe fn thread_pointer() -> u64 {
+    let tp: u64;
+    asm!("movq %fs:0, $0"
+         : "=r"(tp)
+         );
+    tp
+}
+
+/// Anonymously maps `size` zeroed, page-aligned bytes; we avoid the heap here since we're called from the program's threads
+unsafe fn allocate(size: usize) -> Option<u64> {
+    let addr = mmap::mmap(0 as *const u64,
+                          page::page_end(size as u64) as usize,
+                          mmap::PROT_READ | mmap::PROT_WRITE,
+                          (mmap::MAP_PRIVATE | mmap::MAP_ANONYMOUS) as c_int,
+                          -1,
+                          0);
+    if addr == mmap::MAP_FAILED { None } else { Some (addr) }
+}
+
+/// Allocates a DTV with room for `slots` modules; as in glibc, `dtv[-1]` is the number of slots, `dtv[0]` the generation,
+/// and `dtv[id]` the address of module `id`'s block, or 0 if this thread hasn't allocated it yet. Returns `&dtv[0]`, which is what the TCB points at
+unsafe fn allocate_dtv(slots: usize) -> Option<*mut u64> {
+    allocate((slots + 2) * 8).map(|addr| {
+        let dtv = (addr as *mut u64).offset(1);
+        *dtv.offset(-1) = slots as u64;
+        dtv
+    })
+}
+
+/// Unmaps the DTV `allocate_dtv` returned as `dtv`, which has room for `slots` modules
+unsafe fn free_dtv(dtv: *mut u64, slots: usize) {
+    mmap::munmap(dtv.offset(-1) as *const u64, page::page_end(((slots + 2) * 8) as u64) as usize);
+}
+
+extern {
+    /// The TLS descriptor resolvers from `arch/x86/asm.s`; `R_X86_64_TLSDESC` fills a descriptor with one of these and its argument
+    fn _dryad_tlsdesc_static();
+    fn _dryad_tlsdesc_dynamic();
+}
+
+/// The argument to `__tls_get_addr`, which the general-dynamic code sequences build in the GOT with a pair of `DTPMOD64` and `DTPOFF64` relocations
+#[repr(C)]
+pub struct TlsIndex {
+    pub module: u64,
+    pub offset: u64,
+}
+
+/// A loaded object's `PT_TLS` segment, i.e., its TLS initialization image, and where its block lives in the static TLS area
+#[derive(Clone, Copy)]
+pub struct TlsModule {
+    /// the (1-based) module id, which is what `R_X86_64_DTPMOD64` resolves to
+    pub id: usize,
+    /// the index of the object this module belongs to in the link map
+    pub object: usize,
+    /// the in-memory (i.e., biased) address of the initialization image, the `.tdata`
+    pub image: u64,
+    pub filesz: usize,
+    pub memsz: usize,
+    pub align: usize,
+    /// the module's block begins at `tp - offset`, which is what `R_X86_64_TPOFF64` is relative to;
+    /// `None` if the module was loaded after startup, and hence lives only in the DTV
+    pub offset: Option<usize>,
+}
+
+/// Every module, and the layout of the static TLS area they're in
+struct Registry {
+    modules: Vec<TlsModule>,
+    /// how many module ids have been handed out; an id is never reused, since a thread's DTV can still have a block for an unloaded module until the thread next updates it
+    ids: usize,
+    /// the id and block size of every module `unregister`ed since startup, whose blocks each thread frees when it next updates its DTV
+    retired: Vec<(usize, usize)>,
+    /// size of the static TLS area below the thread pointer
+    static_size: usize,
+    /// the largest alignment any module in the static TLS area requires
+    static_align: usize,
+}
+
+pub struct Tls {
+    /// Any thread in the slow path of `__tls_get_addr` reads this while the linker registers and unregisters modules, so it's only ever touched with `LOCK` held, never through a borrow of the `Tls` itself
+    registry: UnsafeCell<Registry>,
+}
+
+impl Tls {
+    pub fn new() -> Tls {
+        Tls {
+            registry: UnsafeCell::new(Registry {
+                modules: Vec::new(),
+                ids: 0,
+                retired: Vec::new(),
+                static_size: 0,
+                static_align: 1,
+            }),
+        }
+    }
+
+    /// Calls `f` with the registry, and `LOCK` held
+    fn with_registry<T, F: FnOnce(&mut Registry) -> T>(&self, f: F) -> T {
+        lock();
+        let result = f(unsafe { &mut *self.registry.get() });
+        unlock();
+        result
+    }
+
+    /// Registers the `PT_TLS` segment, if any, of the object at index `object` in the link map, which was loaded at `bias`;
+    /// assigns it the next module id and a block in the static TLS area, and returns the module id
+    pub fn register(&self, object: usize, bias: u64, phdrs: &[ProgramHeader]) -> Option<usize> {
+        for phdr in phdrs {
+            if phdr.p_type != program_header::PT_TLS {
+                continue
+            }
+            return Some (self.with_registry(|registry| {
+                registry.ids += 1;
+                let id = registry.ids;
+                let align = cmp::max(phdr.p_align as usize, 1);
+                // the block's first byte might need to sit at a non-zero offset within its alignment, if the segment's p_vaddr isn't aligned;
+                // and for the executable (i.e., the first module), this _must_ agree with the local-exec offsets the static linker already baked into its code
+                let firstbyte = (align - (phdr.p_vaddr as usize & (align - 1))) & (align - 1);
+                let offset = round_up(registry.static_size + phdr.p_memsz as usize - firstbyte, align) + firstbyte;
+                registry.static_size = offset;
+                registry.static_align = cmp::max(registry.static_align, align);
+                registry.modules.push(TlsModule {
+                    id: id,
+                    object: object,
+                    image: phdr.p_vaddr + bias,
+                    filesz: phdr.p_filesz as usize,
+                    memsz: phdr.p_memsz as usize,
+                    align: align,
+                    offset: Some (offset),
+                });
+                id
+            }))
+        }
+        None
+    }
+
+    /// Registers the `PT_TLS` segment, if any, of an object loaded _after_ the static TLS area was installed, e.g., by `dlopen`;
+    /// its blocks are only ever allocated lazily by `__tls_get_addr`, and every thread's DTV is now out of date
+    pub fn register_dynamic(&self, object: usize, bias: u64, phdrs: &[ProgramHeader]) -> Option<usize> {
+        for phdr in phdrs {
+            if phdr.p_type != program_header::PT_TLS {
+                continue
+            }
+            return Some (self.with_registry(|registry| {
+                registry.ids += 1;
+                let id = registry.ids;
+                registry.modules.push(TlsModule {
+                    id: id,
+                    object: object,
+                    image: phdr.p_vaddr + bias,
+                    filesz: phdr.p_filesz as usize,
+                    memsz: phdr.p_memsz as usize,
+                    align: cmp::max(phdr.p_align as usize, 1),
+                    offset: None,
+                });
+                GENERATION.fetch_add(1, Ordering::SeqCst);
+                id
+            }))
+        }
+        None
+    }
+
+    /// Forgets the modules of the link map indices `objects`, which `dlclose`, or a failed `dlopen`, is about to unload; their ids are retired, and every thread's blocks for them are freed the next time it calls into the slow path of `__tls_get_addr`, which the new generation guarantees it does
+    pub fn unregister(&self, objects: &[usize]) {
+        self.with_registry(|registry| {
+            let retired = &mut registry.retired;
+            registry.modules.retain(|module| {
+                if !objects.contains(&module.object) {
+                    return true
+                }
+                // a module in the static TLS area is never unloaded, and its block isn't ours to free anyway
+                if module.offset.is_none() {
+                    retired.push((module.id, module.memsz));
+                }
+                false
+            });
+            GENERATION.fetch_add(1, Ordering::SeqCst);
+        })
+    }
+
+    /// Returns the TLS module of the object at index `object` in the link map, if it has one
+    pub fn module(&self, object: usize) -> Option<TlsModule> {
+        self.with_registry(|registry| registry.modules.iter().find(|module| module.object == object).cloned())
+    }
+
+    /// Allocates the initial thread's static TLS area and TCB, copies every module's initialization image into its block (the `.tbss` remainder is already zero),
+    /// seeds the stack and pointer guards from the 16 bytes at `random` (i.e., `AT_RANDOM`), and finally points `%fs` at the TCB; returns the thread pointer.
+    /// This must be the last thing dryad does before transferring control to the program.
+    pub unsafe fn install(&self, random: u64) -> Result<u64, String> {
+        lock();
+        let result = self.install_locked(&*self.registry.get(), random);
+        unlock();
+        result
+    }
+
+    unsafe fn install_locked(&self, registry: &Registry, random: u64) -> Result<u64, String> {
+        let align = cmp::max(registry.static_align, 64);
+        let below = round_up(registry.static_size, align);
+        let size = page::page_end((below + TCB_SIZE) as u64) as usize;
+        let area = mmap::mmap(0 as *const u64,
+                              size,
+                              mmap::PROT_READ | mmap::PROT_WRITE,
+                              (mmap::MAP_PRIVATE | mmap::MAP_ANONYMOUS) as c_int,
+                              -1,
+                              0);
+        if area == mmap::MAP_FAILED {
+            return Err(format!("<dryad> Error: could not allocate {} bytes for the static TLS area", size))
+        }
+
+        let dtv = match allocate_dtv(registry.ids + DTV_SLACK) {
+            Some (dtv) => dtv,
+            None => return Err(format!("<dryad> Error: could not allocate the initial DTV"))
+        };
+        *dtv = GENERATION.load(Ordering::SeqCst) as u64;
+
+        let tp = area + below as u64;
+        for module in &registry.modules {
+            if let Some(offset) = module.offset {
+                let block = tp - offset as u64;
+                ptr::copy_nonoverlapping(module.image as *const u8, block as *mut u8, module.filesz);
+                *dtv.offset(module.id as isize) = block;
+            }
+        }
+
+        let tcb = tp as *mut u64;
+        *tcb.offset(TCB_SELF) = tp;
+        *tcb.offset(TCB_DTV) = dtv as u64;
+        *tcb.offset(TCB_SELF_AGAIN) = tp;
+        if random != 0 {
+            let random = random as *const u64;
+            // like glibc, zero the low byte of the canary so string functions can't leak it
+            *tcb.offset(TCB_STACK_GUARD) = *random & !0xff;
+            *tcb.offset(TCB_POINTER_GUARD) = *random.offset(1);
+        }
+
+        // `__tls_get_addr` needs to find us once the program is running; the linker keeps us boxed, and never drops us
+        REGISTRY = self as *const Tls;
+
+        if utils::arch_prctl(ARCH_SET_FS, tp) != 0 {
+            return Err(format!("<dryad> Error: arch_prctl(ARCH_SET_FS, {:#x}) failed", tp))
+        }
+        Ok (tp)
+    }
+}
+
+/// The `__tls_get_addr` slow path: brings the calling thread's DTV up to date, growing it if module `id` doesn't fit, and freeing its blocks for modules which have been unloaded,
+/// and allocates and initializes the thread's block for module `id` if it hasn't been yet; returns the block's address, or 0 if there's no such module
+unsafe fn update_dtv(tcb: *mut u64, id: usize) -> u64 {
+    lock();
+    let registry = &*(*REGISTRY).registry.get();
+    let mut dtv = *tcb.offset(TCB_DTV) as *mut u64;
+
+    if id > *dtv.offset(-1) as usize {
+        let slots = registry.ids + DTV_SLACK;
+        match allocate_dtv(slots) {
+            Some (new) => {
+                let old_slots = *dtv.offset(-1) as usize;
+                ptr::copy_nonoverlapping(dtv.offset(1), new.offset(1), old_slots);
+                // only the thread it belongs to ever reads its DTV, and that's us
+                free_dtv(dtv, old_slots);
+                dtv = new;
+                *tcb.offset(TCB_DTV) = dtv as u64;
+            },
+            None => {
+                unlock();
+                return 0
+            }
+        }
+    }
+    for &(retired, memsz) in &registry.retired {
+        if retired <= *dtv.offset(-1) as usize && *dtv.offset(retired as isize) != 0 {
+            mmap::munmap(*dtv.offset(retired as isize) as *const u64, page::page_end(memsz as u64) as usize);
+            *dtv.offset(retired as isize) = 0;
+        }
+    }
+    *dtv = GENERATION.load(Ordering::SeqCst) as u64;
+
+    let mut block = *dtv.offset(id as isize);
+    if block == 0 {
+        if let Some(module) = registry.modules.iter().find(|module| module.id == id) {
+            // mmap is page aligned, which more than covers any sane p_align
+            if let Some(addr) = allocate(module.memsz) {
+                ptr::copy_nonoverlapping(module.image as *const u8, addr as *mut u8, module.filesz);
+                *dtv.offset(id as isize) = addr;
+                block = addr;
+            }
+        }
+    }
+    unlock();
+    block
+}
+
+/// Returns the address of the thread local variable described by `ti` for the calling thread; this is what every general-dynamic and local-dynamic TLS access sequence calls
+#[no_mangle]
+pub unsafe extern fn __tls_get_addr(ti: *const TlsIndex) -> *mut u8 {
+    let ti = &*ti;
+    let tcb = thread_pointer() as *mut u64;
+    let dtv = *tcb.offset(TCB_DTV) as *const u64;
+    let id = ti.module as usize;
+
+    // the fast path: nothing was loaded or unloaded since this thread's DTV was updated, the module fits, and this thread already has its block
+    if *dtv == GENERATION.load(Ordering::Relaxed) as u64 && id <= *dtv.offset(-1) as usize {
+        let block = *dtv.offset(id as isize);
+        if block != 0 {
+            return (block + ti.offset) as *mut u8
+        }
+    }
+
+    let block = update_dtv(tcb, id);
+    if block == 0 {
+        0 as *mut u8
+    } else {
+        (block + ti.offset) as *mut u8
+    }
+}
+
+/// Returns the `(resolver, argument)` pair an `R_X86_64_TLSDESC` relocation stores in its GOT descriptor, for a variable at `offset` in `module`'s block.
+/// Modules in the static TLS area get the fast resolver, whose argument is simply the variable's offset from the thread pointer;
+/// anything else gets the dynamic resolver, whose argument is a (leaked) `TlsIndex` for `__tls_get_addr`
+pub fn descriptor(module: &TlsModule, offset: u64) -> (u64, u64) {
+    match module.offset {
+        Some (module_offset) => {
+            (_dryad_tlsdesc_static as u64, offset.wrapping_sub(module_offset as u64))
+        },
+        None => {
+            let ti = Box::new(TlsIndex { module: module.id as u64, offset: offset });
+            (_dryad_tlsdesc_dynamic as u64, Box::into_raw(ti) as u64)
+        }
+    }
+}
@@ -6,10 +6,11 @@ use std::slice;
 
 // TODO: make this a mod like asm::
 
+/// Exits the whole process, i.e., `exit_group`, like libc's `_exit`; plain `exit` would only end the calling thread
 #[no_mangle]
 pub extern fn _exit(code: u64) {
     unsafe {
-        asm!("movq $$60, %rax
+        asm!("movq $$231, %rax
               syscall"
              :
              : "{rdi}"(code)
@@ -17,6 +18,207 @@ pub extern fn _exit(code: u64) {
     }
 }
 
+/// Sets (or gets) architecture-specific thread state, e.g., the `%fs` base; returns 0 on success
+#[inline(always)]
+pub unsafe fn arch_prctl(code: u64, addr: u64) -> i64 {
+    let ret: i64;
+    asm!("movq $$158, %rax
+          syscall"
+         : "={rax}"(ret)
+         : "{rdi}"(code), "{rsi}"(addr)
+         : "rcx", "r11", "memory"
+         : "volatile"
+         );
+    ret
+}
+
+/// Raw `write(2)`; unlike `std::io` it doesn't touch any thread locals, so it's still safe to call once `%fs` belongs to the program
+#[inline(always)]
+pub unsafe fn write_fd(fd: i32, buf: &[u8]) -> i64 {
+    let ret: i64;
+    asm!("movq $$1, %rax
+          syscall"
+         : "={rax}"(ret)
+         : "{rdi}"(fd as i64), "{rsi}"(buf.as_ptr()), "{rdx}"(buf.len())
+         : "rcx", "r11", "memory"
+         : "volatile"
+         );
+    ret
+}
+
+#[inline(always)]
+pub fn getpid() -> u64 {
+    let ret: u64;
+    unsafe {
+        asm!("movq $$39, %rax
+              syscall"
+             : "={rax}"(ret)
+             :
+             : "rcx", "r11"
+             : "volatile"
+             );
+    }
+    ret
+}
+
+/// Logs to the `LD_DEBUG` output if `category` is enabled, e.g., `debug!(debug::RELOC, "relocating {}", name)`
+macro_rules! debug {
+    ($category:expr, $($arg:tt)*) => {
+        if $crate::utils::debug::enabled($category) {
+            $crate::utils::debug::log(format_args!($($arg)*));
+        }
+    }
+}
+
+/// Always writes to stderr; for errors and warnings the user should see whether they asked for debugging output or not
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::utils::debug::error(format_args!($($arg)*));
+    }
+}
+
+/// The `LD_DEBUG` logger.  Each category is a bit in a mask which is set once, from the environment, before anything is loaded; everything is off by default.
+/// Output goes to stderr, or to `LD_DEBUG_OUTPUT.<pid>`, and _never_ to stdout, which belongs to the program
+pub mod debug {
+    use std::cmp;
+    use std::fmt;
+    use std::fmt::Write;
+    use std::fs::OpenOptions;
+    use std::os::unix::io::IntoRawFd;
+
+    use super::{write_fd, getpid, _exit};
+
+    /// Display library search paths
+    pub const LIBS: u32 = 1 << 0;
+    /// Display relocation processing
+    pub const RELOC: u32 = 1 << 1;
+    /// Display progress for input files
+    pub const FILES: u32 = 1 << 2;
+    /// Display symbol table processing
+    pub const SYMBOLS: u32 = 1 << 3;
+    /// Display information about symbol binding
+    pub const BINDINGS: u32 = 1 << 4;
+    /// Display version dependencies
+    pub const VERSIONS: u32 = 1 << 5;
+    /// Display scope information
+    pub const SCOPES: u32 = 1 << 6;
+    /// Display relocation statistics
+    pub const STATISTICS: u32 = 1 << 7;
+    pub const ALL: u32 = LIBS | RELOC | FILES | SYMBOLS | BINDINGS | VERSIONS | SCOPES | STATISTICS;
+
+    const CATEGORIES: [(&'static str, u32, &'static str); 9] = [
+        ("libs", LIBS, "display library search paths"),
+        ("reloc", RELOC, "display relocation processing"),
+        ("files", FILES, "display progress for input file"),
+        ("symbols", SYMBOLS, "display symbol table processing"),
+        ("bindings", BINDINGS, "display information about symbol binding"),
+        ("versions", VERSIONS, "display version dependencies"),
+        ("scopes", SCOPES, "display scope information"),
+        ("statistics", STATISTICS, "display relocation statistics"),
+        ("all", ALL, "all previous options combined"),
+    ];
+
+    static mut MASK: u32 = 0;
+    static mut FD: i32 = 2;
+    static mut PID: u64 = 0;
+
+    /// How long a line of output can get; anything longer is cut off
+    const LINE_SIZE: usize = 1024;
+
+    /// A line of output, formatted on the stack rather than the heap, since the lazy binding trampoline and the finalizers log from the program's threads, after `%fs` stopped being ours
+    struct Line {
+        buf: [u8; LINE_SIZE],
+        len: usize,
+    }
+
+    impl Line {
+        fn new() -> Line {
+            Line { buf: [0; LINE_SIZE], len: 0 }
+        }
+
+        /// Ends the line, and writes it to `fd`
+        unsafe fn write(mut self, fd: i32) {
+            if self.len == LINE_SIZE {
+                self.len -= 1;
+            }
+            self.buf[self.len] = b'\n';
+            write_fd(fd, &self.buf[..self.len + 1]);
+        }
+    }
+
+    impl fmt::Write for Line {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let len = cmp::min(s.len(), LINE_SIZE - self.len);
+            self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+            self.len += len;
+            Ok (())
+        }
+    }
+
+    /// Parses the comma, colon or space separated categories in `LD_DEBUG`; returns the mask, and whether `help` was asked for
+    pub fn parse(var: &str) -> (u32, bool) {
+        let mut mask = 0;
+        let mut help = false;
+        for option in var.split(|c| c == ',' || c == ':' || c == ' ').filter(|option| *option != "") {
+            if option == "help" {
+                help = true;
+                continue
+            }
+            match CATEGORIES.iter().find(|&&(name, _, _)| name == option) {
+                Some (&(_, category, _)) => mask |= category,
+                None => error(format_args!("<dryad> warning: debug option `{}' unknown; try LD_DEBUG=help", option)),
+            }
+        }
+        (mask, help)
+    }
+
+    /// Prints the categories, like `LD_DEBUG=help` does for ld-so, and exits
+    pub fn help() {
+        let mut msg = String::from("Valid options for the LD_DEBUG environment variable are:\n\n");
+        for &(name, _, description) in CATEGORIES.iter() {
+            msg.push_str(&format!("  {:<10} {}\n", name, description));
+        }
+        msg.push_str("  help       display this help message and exit\n\nTo direct the debugging output into a file instead of standard error\na filename can be specified using the LD_DEBUG_OUTPUT environment variable.\n");
+        unsafe { write_fd(1, msg.as_bytes()); }
+        _exit(0);
+    }
+
+    /// Enables the categories in `mask`, sending the output to `<output>.<pid>` if there's an `output`; falls back on stderr if it can't be opened
+    pub fn init(mask: u32, output: Option<&str>) {
+        unsafe {
+            PID = getpid();
+            MASK = mask;
+            if let Some(output) = output {
+                let path = format!("{}.{}", output, PID);
+                match OpenOptions::new().append(true).create(true).open(&path) {
+                    Ok (file) => FD = file.into_raw_fd(),
+                    Err (err) => error(format_args!("<dryad> warning: could not open {}: {}", path, err)),
+                }
+            }
+        }
+    }
+
+    #[inline(always)]
+    pub fn enabled(category: u32) -> bool {
+        unsafe { MASK & category != 0 }
+    }
+
+    /// Writes one line, prefixed with our pid like ld-so does, since the output of several processes often ends up in the same place
+    pub fn log(args: fmt::Arguments) {
+        let mut line = Line::new();
+        unsafe {
+            let _ = write!(line, "{:>5}:\t{}", PID, args);
+            line.write(FD);
+        }
+    }
+
+    pub fn error(args: fmt::Arguments) {
+        let mut line = Line::new();
+        let _ = line.write_fmt(args);
+        unsafe { line.write(2); }
+    }
+}
+
 // this comes from asm.s
 extern {
     pub fn _print(msg: *const u8, len: u64);
@@ -241,6 +443,7 @@ pub mod mmap {
     pub const MAP_DENYWRITE:isize = 0x800;
     pub const MAP_COPY:isize = MAP_PRIVATE | MAP_DENYWRITE;
     pub const MAP_FIXED:isize = 0x10; /* Interpret addr exactly.  */
+    pub const MAP_FIXED_NOREPLACE:isize = 0x100000; /* MAP_FIXED which doesn't unmap underlying mapping.  */
 
     /// map failed, from sys/mman.h, technically ((void *) - 1) ...
     pub const MAP_FAILED:u64 = !0;
@@ -248,6 +451,10 @@ pub mod mmap {
     // from musl libc
     extern {
         fn mmap64(addr: *const u64, len: usize, prot: isize, flags: c_int, fildes: c_int, off: usize) -> u64;
+        #[link_name = "mprotect"]
+        fn _mprotect(addr: *const u64, len: usize, prot: isize) -> c_int;
+        #[link_name = "munmap"]
+        fn _munmap(addr: *const u64, len: usize) -> c_int;
     }
 
     #[inline(always)]
@@ -255,4 +462,16 @@ pub mod mmap {
         mmap64(addr, len, prot, flags, fildes, off)
     }
 
+    /// `addr` must be page aligned; returns 0 on success
+    #[inline(always)]
+    pub unsafe fn mprotect(addr: *const u64, len: usize, prot: isize) -> c_int {
+        _mprotect(addr, len, prot)
+    }
+
+    /// `addr` must be page aligned; returns 0 on success
+    #[inline(always)]
+    pub unsafe fn munmap(addr: *const u64, len: usize) -> c_int {
+        _munmap(addr, len)
+    }
+
 }
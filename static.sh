@@ -29,8 +29,10 @@ $PREFIX/bin/rustc --target=x86_64-unknown-linux-musl src/lib.rs -g --emit obj -o
 #           363e0 __cxa_thread_atexit_impl (182) -> /usr/lib/libc-2.22.so [libc.so.6]
 
 echo -e "linking..."
+# only what's in the dynamic list ends up in dryad's .dynsym, for other objects to bind to; everything in it is also kept from being garbage collected
+KEEP=$(grep -o '[_[:alnum:]]\+;' dryad.dynamic | tr -d ';' | sed 's/^/--undefined=/')
 # using -shared results in DPTMOD64 reloc, and because tls not properly init'd for __tls_get_address (only for local exec) inside of dryad, everything breaks
-ld -pie --gc-sections -I/tmp/$SONAME -L$LIB -soname $SONAME -Bsymbolic -nostdlib -e _start -o $SONAME start.o dryad.o "$RUSTLIB/libstd-$RUSTHASH.rlib" "$RUSTLIB/libcore-$RUSTHASH.rlib" "$RUSTLIB/librand-$RUSTHASH.rlib" "$RUSTLIB/liballoc-$RUSTHASH.rlib" "$RUSTLIB/libcollections-$RUSTHASH.rlib" "$RUSTLIB/librustc_unicode-$RUSTHASH.rlib" "$RUSTLIB/liballoc_system-$RUSTHASH.rlib" "$RUSTLIB/libcompiler-rt.a" musldist/lib/libc.a
+ld -pie --gc-sections --dynamic-list=dryad.dynamic $KEEP -I/tmp/$SONAME -L$LIB -soname $SONAME -Bsymbolic -nostdlib -e _start -o $SONAME start.o dryad.o "$RUSTLIB/libstd-$RUSTHASH.rlib" "$RUSTLIB/libcore-$RUSTHASH.rlib" "$RUSTLIB/librand-$RUSTHASH.rlib" "$RUSTLIB/liballoc-$RUSTHASH.rlib" "$RUSTLIB/libcollections-$RUSTHASH.rlib" "$RUSTLIB/librustc_unicode-$RUSTHASH.rlib" "$RUSTLIB/liballoc_system-$RUSTHASH.rlib" "$RUSTLIB/libcompiler-rt.a" musldist/lib/libc.a
 
 # use this when fixed: https://internals.rust-lang.org/t/static-binary-support-in-rust/2011/55
 #"$RUSTLIB/liblibc-$RUSTHASH.rlib"